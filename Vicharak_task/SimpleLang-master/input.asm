@@ -8,12 +8,12 @@ sta 2
 lda 1
 mov B M 2
 cmp
-jnz %else_branch
+jnz %else_0
 lda 2
 ldi B 1
 add
 sta 2
-jmp %endif
-else_branch:
-endif:
+jmp %endif_0
+else_0:
+endif_0:
 hlt
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Two-pass assembler for the 8-bit CPU.
+//
+// The first pass reads the listing, records where every label points and
+// checks each instruction's operands. The second pass lays the instructions
+// out in ROM, turns label references into byte addresses and encodes them.
+//
+// Every instruction starts with a one-byte opcode. Instructions that take a
+// value, a data address or a jump target are followed by one operand byte:
+//
+//   ldi A n   0x10 n        add      0x30         jmp  t   0x40 t
+//   ldi B n   0x11 n        sub      0x31         jz   t   0x41 t
+//   lda d     0x20 d        cmp      0x32         jnz  t   0x42 t
+//   sta d     0x21 d                              jc   t   0x43 t
+//   mov A M d 0x20 d        call t   0x50 t       jnc  t   0x44 t
+//   mov B M d 0x22 d        ret      0x51
+//   mov M A d 0x21 d        hlt      0xFF
+//   mov M B d 0x23 d
+//
+// `lda`/`sta` are the A forms of `mov`, so they share an opcode. The ROM is
+// 256 bytes and jump targets are ROM byte addresses.
+
+pub const ROM_SIZE: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reg {
+    A,
+    B,
+}
+
+// A decoded instruction. Jump and call targets are indices into
+// `Program::instructions`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Instruction {
+    Ldi(Reg, u8),
+    Lda(u8),
+    Sta(u8),
+    MovRegMem(Reg, u8),
+    MovMemReg(u8, Reg),
+    Add,
+    Sub,
+    Cmp,
+    Jmp(usize),
+    Jz(usize),
+    Jnz(usize),
+    Jc(usize),
+    Jnc(usize),
+    Call(usize),
+    Ret,
+    Hlt,
+}
+
+impl Instruction {
+    // Number of ROM bytes the encoded instruction takes.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Add | Instruction::Sub | Instruction::Cmp | Instruction::Ret | Instruction::Hlt => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmError {
+    pub message: String,
+    // 1-based line in the assembly listing, when the error belongs to one.
+    pub line: Option<usize>,
+}
+
+impl AsmError {
+    fn at(line: usize, message: String) -> Self {
+        AsmError { message, line: Some(line) }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+}
+
+impl Program {
+    // First pass: reads an assembly listing. Labels are collected before any
+    // operand is resolved so that jumps may refer forward.
+    pub fn parse(asm: &str) -> Result<Program, AsmError> {
+        let mut labels = HashMap::new();
+        let mut lines = Vec::new();
+        for (i, raw) in asm.lines().enumerate() {
+            let line = raw.split(';').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with('.') {
+                continue;
+            }
+            if let Some(label) = line.strip_suffix(':') {
+                if labels.insert(label.to_string(), lines.len()).is_some() {
+                    return Err(AsmError::at(i + 1, format!("label '{}' defined more than once", label)));
+                }
+            } else {
+                lines.push((i + 1, line));
+            }
+        }
+
+        let mut instructions = Vec::new();
+        for (line_no, line) in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let target = |idx: usize| -> Result<usize, AsmError> {
+                let operand = parts.get(idx).ok_or_else(|| AsmError::at(line_no, format!("'{}' needs a label", parts[0])))?;
+                let name = operand.strip_prefix('%').unwrap_or(operand);
+                labels.get(name).copied().ok_or_else(|| AsmError::at(line_no, format!("undefined label '{}'", name)))
+            };
+            let byte = |idx: usize| -> Result<u8, AsmError> {
+                let operand = parts.get(idx).ok_or_else(|| AsmError::at(line_no, format!("'{}' is missing an operand", parts[0])))?;
+                operand.parse().map_err(|_| AsmError::at(line_no, format!("'{}' is not a value between 0 and 255", operand)))
+            };
+            let reg = |idx: usize| -> Result<Reg, AsmError> {
+                match parts.get(idx) {
+                    Some(&"A") => Ok(Reg::A),
+                    Some(&"B") => Ok(Reg::B),
+                    _ => Err(AsmError::at(line_no, format!("expected register A or B in '{}'", line))),
+                }
+            };
+            let instruction = match parts[0] {
+                "ldi" => Instruction::Ldi(reg(1)?, byte(2)?),
+                "lda" => Instruction::Lda(byte(1)?),
+                "sta" => Instruction::Sta(byte(1)?),
+                "mov" if parts.get(2) == Some(&"M") => Instruction::MovRegMem(reg(1)?, byte(3)?),
+                "mov" if parts.get(1) == Some(&"M") => Instruction::MovMemReg(byte(3)?, reg(2)?),
+                "add" => Instruction::Add,
+                "sub" => Instruction::Sub,
+                "cmp" => Instruction::Cmp,
+                "jmp" => Instruction::Jmp(target(1)?),
+                "jz" => Instruction::Jz(target(1)?),
+                "jnz" => Instruction::Jnz(target(1)?),
+                "jc" => Instruction::Jc(target(1)?),
+                "jnc" => Instruction::Jnc(target(1)?),
+                "call" => Instruction::Call(target(1)?),
+                "ret" => Instruction::Ret,
+                "hlt" => Instruction::Hlt,
+                _ => return Err(AsmError::at(line_no, format!("unknown instruction '{}'", line))),
+            };
+            let operands = match instruction {
+                Instruction::Add | Instruction::Sub | Instruction::Cmp | Instruction::Ret | Instruction::Hlt => 0,
+                Instruction::Ldi(..) => 2,
+                Instruction::MovRegMem(..) | Instruction::MovMemReg(..) => 3,
+                _ => 1,
+            };
+            if parts.len() - 1 > operands {
+                let takes = match operands {
+                    0 => "no operands".to_string(),
+                    1 => "1 operand".to_string(),
+                    n => format!("{} operands", n),
+                };
+                return Err(AsmError::at(line_no, format!("'{}' takes {}", parts[0], takes)));
+            }
+            instructions.push(instruction);
+        }
+        Ok(Program { instructions })
+    }
+}
+
+// Second pass: lays the program out in ROM and encodes it into a flat image.
+pub fn assemble(program: &Program) -> Result<Vec<u8>, AsmError> {
+    // ROM address of every instruction, plus one past the last for labels
+    // that sit at the very end of the listing.
+    let mut addresses = Vec::with_capacity(program.instructions.len() + 1);
+    let mut next = 0;
+    for instruction in &program.instructions {
+        addresses.push(next);
+        next += instruction.size();
+    }
+    addresses.push(next);
+    if next > ROM_SIZE {
+        return Err(AsmError {
+            message: format!("program needs {} bytes but the ROM holds {}", next, ROM_SIZE),
+            line: None,
+        });
+    }
+
+    // A label just past the last instruction of a full ROM is at 256, which
+    // no operand byte can hold.
+    let target = |t: usize| {
+        u8::try_from(addresses[t]).map_err(|_| AsmError {
+            message: format!("jump target at address {} is past the end of the ROM", addresses[t]),
+            line: None,
+        })
+    };
+    let mut image = Vec::with_capacity(next);
+    for instruction in &program.instructions {
+        let (opcode, operand) = match *instruction {
+            Instruction::Ldi(Reg::A, val) => (0x10, Some(val)),
+            Instruction::Ldi(Reg::B, val) => (0x11, Some(val)),
+            Instruction::Lda(addr) | Instruction::MovRegMem(Reg::A, addr) => (0x20, Some(addr)),
+            Instruction::Sta(addr) | Instruction::MovMemReg(addr, Reg::A) => (0x21, Some(addr)),
+            Instruction::MovRegMem(Reg::B, addr) => (0x22, Some(addr)),
+            Instruction::MovMemReg(addr, Reg::B) => (0x23, Some(addr)),
+            Instruction::Add => (0x30, None),
+            Instruction::Sub => (0x31, None),
+            Instruction::Cmp => (0x32, None),
+            Instruction::Jmp(t) => (0x40, Some(target(t)?)),
+            Instruction::Jz(t) => (0x41, Some(target(t)?)),
+            Instruction::Jnz(t) => (0x42, Some(target(t)?)),
+            Instruction::Jc(t) => (0x43, Some(target(t)?)),
+            Instruction::Jnc(t) => (0x44, Some(target(t)?)),
+            Instruction::Call(t) => (0x50, Some(target(t)?)),
+            Instruction::Ret => (0x51, None),
+            Instruction::Hlt => (0xFF, None),
+        };
+        image.push(opcode);
+        image.extend(operand);
+    }
+    Ok(image)
+}
+
+// A ROM image in Intel HEX, the text format EPROM programmers load: data
+// records of up to 16 bytes, then the end-of-file record. Each record ends
+// in a checksum that makes its bytes sum to zero.
+pub fn intel_hex(image: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in image.chunks(16).enumerate() {
+        let address = i * 16;
+        let mut record = vec![chunk.len() as u8, (address >> 8) as u8, address as u8, 0x00];
+        record.extend_from_slice(chunk);
+        let checksum = record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+        out.push(':');
+        for b in record.iter().chain([&checksum]) {
+            out += &format!("{:02X}", b);
+        }
+        out.push('\n');
+    }
+    out += ":00000001FF\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_text(asm: &str) -> Result<Vec<u8>, AsmError> {
+        assemble(&Program::parse(asm)?)
+    }
+
+    #[test]
+    fn test_encodes_input_listing() {
+        let asm = ".text\nldi A 42\nsta 1\nlda 1\nmov B M 2\ncmp\njnz %else_0\njmp %endif_0\nelse_0:\nendif_0:\nhlt\n";
+        assert_eq!(
+            assemble_text(asm).unwrap(),
+            vec![0x10, 42, 0x21, 1, 0x20, 1, 0x22, 2, 0x32, 0x42, 13, 0x40, 13, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_backward_jumps_and_calls() {
+        let asm = "loop:\nldi B 1\nsub\njnz %loop\ncall %f\nhlt\nf:\nmov M B 3\nret\n";
+        assert_eq!(
+            assemble_text(asm).unwrap(),
+            vec![0x11, 1, 0x31, 0x42, 0, 0x50, 8, 0xFF, 0x23, 3, 0x51]
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_line() {
+        let err = assemble_text(".text\nldi A 1\nfoo 3\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 3: unknown instruction 'foo 3'");
+        let err = assemble_text("ldi A 256\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '256' is not a value between 0 and 255");
+        let err = assemble_text("hlt\njmp %else_branch\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: undefined label 'else_branch'");
+        let err = assemble_text("x:\nx:\nhlt\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: label 'x' defined more than once");
+        let err = assemble_text("ldi A 1\ncmp 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: 'cmp' takes no operands");
+        let err = assemble_text("hlt extra\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: 'hlt' takes no operands");
+        let err = assemble_text("ldi A 1 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: 'ldi' takes 2 operands");
+        let err = assemble_text("x:\njmp %x %x\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: 'jmp' takes 1 operand");
+        let err = assemble_text("mov B M 1 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: 'mov' takes 3 operands");
+    }
+
+    #[test]
+    fn test_program_must_fit_in_rom() {
+        let asm = "ldi A 1\n".repeat(129);
+        let err = assemble_text(&asm).unwrap_err();
+        assert_eq!(err.to_string(), "program needs 258 bytes but the ROM holds 256");
+    }
+
+    #[test]
+    fn test_jump_targets_must_be_in_rom() {
+        // 256 bytes exactly, so `end` would be address 256.
+        let asm = format!("{}jmp %end\nend:\n", "ldi A 1\n".repeat(127));
+        let err = assemble_text(&asm).unwrap_err();
+        assert_eq!(err.to_string(), "jump target at address 256 is past the end of the ROM");
+        let asm = format!("{}end:\njmp %end\n", "ldi A 1\n".repeat(127));
+        assert_eq!(assemble_text(&asm).unwrap()[254..], [0x40, 254]);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let image: Vec<u8> = (0..18).collect();
+        assert_eq!(
+            intel_hex(&image),
+            ":10000000000102030405060708090A0B0C0D0E0F78\n:020010001011CD\n:00000001FF\n"
+        );
+        assert_eq!(intel_hex(&[]), ":00000001FF\n");
+    }
+}
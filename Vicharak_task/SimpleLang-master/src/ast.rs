@@ -1,278 +1,213 @@
-
-use std::collections::HashMap;
-use std::io;
-use std::sync::Mutex;
-use std::rc::Rc;
-use std::io::Write;
-use lazy_static::lazy_static;
-
-lazy_static! {
-    static ref MEM_LOC: Mutex<HashMap<String, i32>> = Mutex::new(HashMap::new());
-    static ref COUNTER: Mutex<i32> = Mutex::new(1);
-}
-
-pub struct NumberNode {
-    val: i32,
-}
-
-impl NumberNode {
-    pub fn new(val: i32) -> Self {
-        NumberNode { val }
-    }
-
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        writeln!(out, "ldi A {}", self.val)?;
-        println!("ldi A {}", self.val);
-        println!("generating in b number node");
-        Ok(())
-    }
-
-    pub fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        writeln!(out, "ldi A {}", self.val)?;
-        println!("ldi A {}", self.val);
-        println!("generating in l number node");
-        Ok(())
-    }
-
-    pub fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        writeln!(out, "ldi B {}", self.val)?;
-        println!("ldi B {}", self.val);
-        println!("generating in r number node");
-        Ok(())
+use crate::diagnostic::Span;
+
+// The syntax tree. Nodes are plain data with public fields, so each pass
+// (lowering to IR in `ir.rs`, evaluation in `interp.rs`, and anything
+// written against `Visitor`/`VisitorMut`) matches on them directly.
+
+pub enum Expr {
+    Number { val: u8, span: Span },
+    Variable { name: String, span: Span },
+    // `op` is the operator's source text, e.g. "+" or "<=".
+    Binary { l: Box<Expr>, op: String, r: Box<Expr>, span: Span },
+    // Only `-` for now, which means `0 - operand`.
+    Unary { op: String, operand: Box<Expr>, span: Span },
+}
+
+impl Expr {
+    pub fn binary(l: Expr, op: String, r: Expr) -> Self {
+        let span = l.span().to(r.span());
+        Expr::Binary { l: Box::new(l), op, r: Box::new(r), span }
+    }
+
+    // Source range the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number { span, .. }
+            | Expr::Variable { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Unary { span, .. } => *span,
+        }
     }
 }
 
-pub struct VariableNode {
-    name: String,
-    id: i32,
-}
-
-impl VariableNode {
-    pub fn new(name: String) -> Self {
-        let mut mem_loc = MEM_LOC.lock().unwrap();
-        let mut counter = COUNTER.lock().unwrap();
-
-        if !mem_loc.contains_key(&name) {
-            mem_loc.insert(name.clone(), *counter);
-            *counter += 1;
+pub enum Stmt {
+    // `int name = val;`. `val` is `None` for `int name;`, which starts at 0.
+    Declare { name: String, val: Option<Expr>, span: Span },
+    Assign { name: String, val: Expr, span: Span },
+    If { cond: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>, span: Span },
+    While { cond: Expr, body: Box<Stmt>, span: Span },
+    // A `{ ... }` block, or the whole program.
+    Block { stmts: Vec<Stmt>, span: Span },
+    // An expression evaluated for nothing but its (discarded) value.
+    Expr(Expr),
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Declare { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Block { span, .. } => *span,
+            Stmt::Expr(expr) => expr.span(),
         }
-
-        let id = mem_loc[&name];
-        VariableNode { name, id }
-    }
-
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("generating in b VariableNode");
-        writeln!(out, "sta {}", self.id)?;
-        Ok(())
-    }
-
-    pub fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("in variable node L for variable {}", self.name);
-        writeln!(out, "lda {}", self.id)?;
-        Ok(())
-    }
-
-    pub fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("in variable node R");
-        writeln!(out, "mov B M {}", self.id)?;
-        Ok(())
-    }
-    
-    pub fn is_declared(name: &str) -> bool {
-        MEM_LOC.lock().unwrap().contains_key(name)
     }
 }
 
-pub struct BinaryOpNode {
-    l: Rc<dyn ASTNode>,
-    op: String,
-    r: Rc<dyn ASTNode>,
-}
-
-impl BinaryOpNode {
-    pub fn new(l: Rc<dyn ASTNode>, op: String, r: Rc<dyn ASTNode>) -> Self {
-        BinaryOpNode { l, op, r }
-    }
-
-    pub fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out).expect("error in binary node");
-        Ok(())
+// Read-only traversal. Override the methods for the nodes a pass cares about
+// and call the matching `walk_*` function to keep descending into children.
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
     }
 
-    pub fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out).expect("error in binary node");
-        Ok(())
-    }
-
-
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("in binary node");
-        self.l.generate_l(out)?;
-        //print what the type of r is 
-        self.r.generate_r(out)?;
-        match self.op.as_str() {
-
-            "+" =>{println!("Adding");
-                  writeln!(out, "add")?},
-            "-" => writeln!(out, "sub")?,
-            "==" => writeln!(out, "cmp")?,
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported binary op")),
-        };
-        Ok(())
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
     }
 }
 
-pub struct VariableDec {
-    var_name: String,
-    val: Rc<dyn ASTNode>,
-}
-
-impl VariableDec {
-    pub fn new(var_name: String, val: Rc<dyn ASTNode>) -> Self {
-        let mut mem_loc = MEM_LOC.lock().unwrap();
-        if !mem_loc.contains_key(&var_name) {
-            let mut counter = COUNTER.lock().unwrap();
-            mem_loc.insert(var_name.clone(), *counter);
-            *counter += 1;
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Declare { val, .. } => {
+            if let Some(val) = val {
+                visitor.visit_expr(val);
+            }
         }
-        VariableDec { var_name, val }
-    }
-
-    fn get_memory_location(&self) -> i32 {
-        MEM_LOC.lock().unwrap()[&self.var_name]
-    }
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("variable declaration in b");
-
-        // Generate code for the right-hand side value (which could be a BinaryOpNode)
-        self.val.generate_code(out)?;
-
-        println!("storing value to memory location");
-        writeln!(out, "sta {}", self.get_memory_location())?; // Store the result in memory for 'y'
-        Ok(())
+        Stmt::Assign { val, .. } => visitor.visit_expr(val),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            visitor.visit_expr(cond);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::While { cond, body, .. } => {
+            visitor.visit_expr(cond);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Block { stmts, .. } => {
+            for stmt in stmts {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::Expr(expr) => visitor.visit_expr(expr),
     }
 }
-pub struct ConditionalNode {
-    cond: Rc<dyn ASTNode>,
-    then_branch: Rc<dyn ASTNode>,
-    else_branch: Option<Rc<dyn ASTNode>>,
-}
-
-impl ConditionalNode {
-    pub fn new(cond: Rc<dyn ASTNode>, then_branch: Rc<dyn ASTNode>, else_branch: Option<Rc<dyn ASTNode>>) -> Self {
-        ConditionalNode { cond, then_branch, else_branch }
-    }
-
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.cond.generate_code(out)?;
-        writeln!(out, "jnz %else_branch")?;
-        println!("jnz %else_branch");
 
-        self.then_branch.generate_code(out)?;
-        writeln!(out, "jmp %endif")?;
-        println!("jmp %endif");
-
-        writeln!(out, "else_branch:")?;
-        println!("else_branch:");
-
-        if let Some(ref else_br) = self.else_branch {
-            else_br.generate_code(out)?;
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binary { l, r, .. } => {
+            visitor.visit_expr(l);
+            visitor.visit_expr(r);
         }
-        writeln!(out, "endif:")?;
-        println!("endif:");
-        Ok(())
+        Expr::Unary { operand, .. } => visitor.visit_expr(operand),
+        Expr::Number { .. } | Expr::Variable { .. } => {}
     }
 }
 
-pub struct BlockNode {
-    statements: Vec<Rc<dyn ASTNode>>,
-}
-
-impl BlockNode {
-    pub fn new() -> Self {
-        BlockNode {
-            statements: Vec::new(),
-        }
+// In-place transformation. Same shape as `Visitor`, but nodes may be
+// rewritten or replaced wholesale.
+pub trait VisitorMut {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
     }
 
-    pub fn add_stat(&mut self, statement: Rc<dyn ASTNode>) {
-        self.statements.push(statement);
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
     }
+}
 
-    pub fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        println!("started generating code");
-        for statement in &self.statements {
-            statement.generate_code(out)?;
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Declare { val, .. } => {
+            if let Some(val) = val {
+                visitor.visit_expr_mut(val);
+            }
+        }
+        Stmt::Assign { val, .. } => visitor.visit_expr_mut(val),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt_mut(else_branch);
+            }
+        }
+        Stmt::While { cond, body, .. } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::Block { stmts, .. } => {
+            for stmt in stmts {
+                visitor.visit_stmt_mut(stmt);
+            }
         }
-        println!("ended generating code");
-        Ok(())
+        Stmt::Expr(expr) => visitor.visit_expr_mut(expr),
     }
 }
-//add trait debug
 
-pub trait ASTNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()>;
-    fn generate_l(&self, _out: &mut dyn Write) -> io::Result<()> { Ok(()) }
-    fn generate_r(&self, _out: &mut dyn Write) -> io::Result<()> { Ok(()) }
-}
-
-
-impl ASTNode for NumberNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
-    }
-    fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_l(out)
-    }
-
-    fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_r(out)
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Binary { l, r, .. } => {
+            visitor.visit_expr_mut(l);
+            visitor.visit_expr_mut(r);
+        }
+        Expr::Unary { operand, .. } => visitor.visit_expr_mut(operand),
+        Expr::Number { .. } | Expr::Variable { .. } => {}
     }
-
 }
 
-impl ASTNode for VariableNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
 
-    fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_l(out)
-    }
+    // Collects every variable read, in visiting order.
+    struct Reads(Vec<String>);
 
-    fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_r(out)
+    impl Visitor for Reads {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let Expr::Variable { name, .. } = expr {
+                self.0.push(name.clone());
+            }
+            walk_expr(self, expr);
+        }
     }
-}
 
-impl ASTNode for BinaryOpNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
-    }
-    fn generate_l(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_l(out)
+    #[test]
+    fn test_visitor_reaches_every_expression() {
+        let ast = parse("int a = 1; int b = a + 2; if (a < b) { b = a * (b - a); } else { while (b) { b = b - 1; } }");
+        let mut reads = Reads(Vec::new());
+        reads.visit_stmt(&ast);
+        assert_eq!(reads.0, vec!["a", "a", "b", "a", "b", "a", "b", "b"]);
     }
 
-    fn generate_r(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_r(out)
-    }
-}
+    // Doubles every number literal.
+    struct Double;
 
-impl ASTNode for VariableDec {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
+    impl VisitorMut for Double {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let Expr::Number { val, .. } = expr {
+                *val = val.wrapping_mul(2);
+            }
+            walk_expr_mut(self, expr);
+        }
     }
-}
 
-impl ASTNode for ConditionalNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
+    #[test]
+    fn test_visitor_mut_rewrites_in_place() {
+        let mut ast = parse("int a = 3; if (a == 3) { a = a + 100; }");
+        Double.visit_stmt_mut(&mut ast);
+        let env = crate::interp::interpret(&ast, 100).unwrap();
+        assert_eq!(env.variables(), vec![("a", 206)]);
     }
-}
 
-impl ASTNode for BlockNode {
-    fn generate_code(&self, out: &mut dyn Write) -> io::Result<()> {
-        self.generate_code(out)
+    #[test]
+    fn test_spans() {
+        let ast = parse("int a = 1;\na = a + 22;");
+        let Stmt::Block { stmts, .. } = &ast else { panic!("program is not a block") };
+        let Stmt::Assign { val, .. } = &stmts[1] else { panic!("expected an assignment") };
+        assert_eq!(val.span(), Span::new(15, 21, 2, 5));
+        assert_eq!(stmts[1].span(), Span::new(11, 22, 2, 1));
     }
 }
@@ -0,0 +1,240 @@
+use compiler::printer::AstFormat;
+use compiler::{CompileOptions, MAX_OPT_LEVEL};
+
+// Command-line parsing. Options may come before or after the subcommand and
+// the input; an input of `-`, or none at all, means stdin.
+
+pub const USAGE: &str = "\
+Usage: compiler [build] [options] [<input>]   compile a program (the default)
+       compiler check [<input>]               report errors without compiling
+       compiler run [<input>]                 compile and run on the CPU model
+       compiler eval [<input>]                evaluate with the reference interpreter
+       compiler fmt [--check] [<input>]       format a file in place
+       compiler fuzz <iterations>             fuzz the compiler
+
+Options:
+  -o, --output <path>   where to write the output; `-` is stdout
+  --emit <kind>         what `build` writes: tokens, ast, ast-json, ir, asm (the default), bin or hex
+  -O0, -O1, -O2         optimization level: -O0 (the default) compiles the program as written,
+                        -O1 folds constant expressions and branches, and -O2 also removes
+                        redundant instructions from the listing
+  --check               with `fmt`: fail if the input is not formatted, change nothing
+  -v, -vv, -vvv         verbose output on stderr: files written and tokens, parser trace, codegen trace
+  --trace-codegen       pair every emitted line with the IR behind it, on stderr
+
+Without -o, asm, bin and hex go next to the input with that extension, and
+everything else goes to stdout; reading from stdin, everything goes to stdout.";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command {
+    Build,
+    Check,
+    Run,
+    Eval,
+    Fmt,
+    Fuzz,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Emit {
+    Tokens,
+    Ast(AstFormat),
+    Ir,
+    Asm,
+    Bin,
+    Hex,
+}
+
+impl Emit {
+    fn parse(kind: &str) -> Result<Emit, String> {
+        Ok(match kind {
+            "tokens" => Emit::Tokens,
+            "ast" => Emit::Ast(AstFormat::Text),
+            "ast-json" => Emit::Ast(AstFormat::Json),
+            "ir" => Emit::Ir,
+            "asm" => Emit::Asm,
+            "bin" => Emit::Bin,
+            "hex" => Emit::Hex,
+            _ => return Err(format!("unknown --emit kind '{}'", kind)),
+        })
+    }
+
+    // The extension of the file written next to the input, for the kinds
+    // that are written to a file by default.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Emit::Asm => Some("asm"),
+            Emit::Bin => Some("bin"),
+            Emit::Hex => Some("hex"),
+            Emit::Tokens | Emit::Ast(_) | Emit::Ir => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    // The input file, or `None` for stdin. For `fuzz`, the iteration count.
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub check: bool,
+    pub opt_level: u8,
+    pub verbosity: usize,
+    pub trace_codegen: bool,
+}
+
+// Parses the arguments after the program name.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut emit = None;
+    let mut check = false;
+    // Without -O, compile as the library does by default.
+    let mut opt_level = CompileOptions::default().opt_level;
+    let mut verbosity = 0;
+    let mut trace_codegen = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbosity += 1,
+            "-vv" | "-vvv" => verbosity += arg.len() - 1,
+            "--trace-codegen" => trace_codegen = true,
+            "--check" => check = true,
+            _ if arg.starts_with("-O") => {
+                opt_level = arg["-O".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|&level| level <= MAX_OPT_LEVEL)
+                    .ok_or_else(|| format!("unknown optimization level '{}'", arg))?;
+            }
+            "-o" | "--output" => output = Some(args.next().ok_or("-o needs a path")?.clone()),
+            "--emit" => emit = Some(Emit::parse(args.next().ok_or("--emit needs a kind")?)?),
+            _ if arg.starts_with("--emit=") => emit = Some(Emit::parse(&arg["--emit=".len()..])?),
+            // Older spellings of `--emit ast` and `--emit ast-json`.
+            "--dump-ast" | "--dump-ast=text" => emit = Some(Emit::Ast(AstFormat::Text)),
+            "--dump-ast=json" => emit = Some(Emit::Ast(AstFormat::Json)),
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    // `assemble <input>` is the older spelling of `build --emit bin`. On its
+    // own, `assemble` is the name of the input file.
+    if positional.len() > 1 && positional[0] == "assemble" {
+        positional[0] = "build".to_string();
+        emit = emit.or(Some(Emit::Bin));
+    }
+
+    let command = match positional.first().map(String::as_str) {
+        Some("build") => Command::Build,
+        Some("check") => Command::Check,
+        Some("run") => Command::Run,
+        Some("eval") => Command::Eval,
+        Some("fmt") => Command::Fmt,
+        Some("fuzz") => Command::Fuzz,
+        _ => {
+            positional.insert(0, "build".to_string());
+            Command::Build
+        }
+    };
+    let name = positional.remove(0);
+    if positional.len() > 1 {
+        return Err(format!("'{}' takes one input, got {}", name, positional.len()));
+    }
+    let input = positional.pop().filter(|input| input != "-");
+    if command == Command::Fuzz && input.is_none() {
+        return Err("'fuzz' needs an iteration count".to_string());
+    }
+    if emit.is_some() && command != Command::Build {
+        return Err(format!("--emit only applies to 'build', not '{}'", name));
+    }
+    if output.is_some() && !matches!(command, Command::Build | Command::Fmt) {
+        return Err(format!("-o only applies to 'build' and 'fmt', not '{}'", name));
+    }
+    if check && command != Command::Fmt {
+        return Err(format!("--check only applies to 'fmt', not '{}'", name));
+    }
+
+    Ok(Options {
+        command,
+        input,
+        output,
+        emit: emit.unwrap_or(Emit::Asm),
+        check,
+        opt_level,
+        verbosity,
+        trace_codegen,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_bare_input_builds_assembly() {
+        let options = parse("prog.txt").unwrap();
+        assert_eq!(options.command, Command::Build);
+        assert_eq!(options.input.as_deref(), Some("prog.txt"));
+        assert_eq!((options.emit, options.output), (Emit::Asm, None));
+    }
+
+    #[test]
+    fn test_options_in_any_position() {
+        let options = parse("-v build --emit hex prog.txt -o out.hex -vv --trace-codegen").unwrap();
+        assert_eq!(options.command, Command::Build);
+        assert_eq!(options.emit, Emit::Hex);
+        assert_eq!(options.output.as_deref(), Some("out.hex"));
+        assert_eq!(options.verbosity, 3);
+        assert!(options.trace_codegen);
+        assert_eq!(options.opt_level, 0);
+        assert_eq!(parse("-O2 run prog.txt").unwrap().opt_level, 2);
+        assert_eq!(parse("prog.txt -O1").unwrap().opt_level, 1);
+        assert_eq!(parse("--emit=ast-json -").unwrap().emit, Emit::Ast(AstFormat::Json));
+    }
+
+    #[test]
+    fn test_stdin_input() {
+        assert_eq!(parse("").unwrap().input, None);
+        assert_eq!(parse("run -").unwrap().input, None);
+        assert_eq!(parse("fmt --check").unwrap(), Options {
+            command: Command::Fmt,
+            input: None,
+            output: None,
+            emit: Emit::Asm,
+            check: true,
+            opt_level: 0,
+            verbosity: 0,
+            trace_codegen: false,
+        });
+    }
+
+    #[test]
+    fn test_older_spellings() {
+        let options = parse("assemble prog.txt").unwrap();
+        assert_eq!((options.command, options.emit), (Command::Build, Emit::Bin));
+        let options = parse("-v assemble").unwrap();
+        assert_eq!((options.command, options.emit), (Command::Build, Emit::Asm));
+        assert_eq!(options.input.as_deref(), Some("assemble"));
+        assert_eq!(parse("--dump-ast=json prog.txt").unwrap().emit, Emit::Ast(AstFormat::Json));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert_eq!(parse("--emit wasm a.txt").unwrap_err(), "unknown --emit kind 'wasm'");
+        assert_eq!(parse("a.txt -o").unwrap_err(), "-o needs a path");
+        assert_eq!(parse("a.txt b.txt").unwrap_err(), "'build' takes one input, got 2");
+        assert_eq!(parse("run --emit bin a.txt").unwrap_err(), "--emit only applies to 'build', not 'run'");
+        assert_eq!(parse("check -o x a.txt").unwrap_err(), "-o only applies to 'build' and 'fmt', not 'check'");
+        assert_eq!(parse("build --check a.txt").unwrap_err(), "--check only applies to 'fmt', not 'build'");
+        assert_eq!(parse("fuzz").unwrap_err(), "'fuzz' needs an iteration count");
+        assert_eq!(parse("-O9 a.txt").unwrap_err(), "unknown optimization level '-O9'");
+        assert_eq!(parse("--frobnicate").unwrap_err(), "unknown option '--frobnicate'");
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use crate::log::CodegenTrace;
+use crate::runtime::RuntimeLibrary;
+
+// The highest data address an instruction can name.
+pub const MAX_ADDRESS: i32 = 255;
+
+// Maps variable names to the memory cells they live in. Addresses start at 1
+// and are handed out in declaration order, up to `MAX_ADDRESS`. Code
+// generation also borrows unnamed temporary cells from the same address space.
+pub struct SymbolTable {
+    locations: HashMap<String, i32>,
+    next_location: i32,
+    free_temps: Vec<i32>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            locations: HashMap::new(),
+            next_location: 1,
+            free_temps: Vec::new(),
+        }
+    }
+
+    // Hands out a scratch cell for an intermediate result, reusing released
+    // ones before growing memory.
+    pub fn acquire_temp(&mut self) -> io::Result<i32> {
+        if let Some(loc) = self.free_temps.pop() {
+            return Ok(loc);
+        }
+        self.allocate("a temporary")
+    }
+
+    pub fn release_temp(&mut self, loc: i32) {
+        self.free_temps.push(loc);
+    }
+
+    // Returns the address of `name`, allocating a fresh cell the first time it is seen.
+    pub fn declare(&mut self, name: &str) -> io::Result<i32> {
+        if let Some(&loc) = self.locations.get(name) {
+            return Ok(loc);
+        }
+        let loc = self.allocate(&format!("'{}'", name))?;
+        self.locations.insert(name.to_string(), loc);
+        Ok(loc)
+    }
+
+    // The next unused cell, for `what`.
+    fn allocate(&mut self, what: &str) -> io::Result<i32> {
+        if self.next_location > MAX_ADDRESS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no data memory left for {}: all {} cells are in use", what, MAX_ADDRESS),
+            ));
+        }
+        let loc = self.next_location;
+        self.next_location += 1;
+        Ok(loc)
+    }
+
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.locations.contains_key(name)
+    }
+
+    // Program variables and their addresses in declaration order. Cells the
+    // runtime library reserved for itself are left out.
+    pub fn variables(&self) -> Vec<(&str, i32)> {
+        let mut vars: Vec<(&str, i32)> = self
+            .locations
+            .iter()
+            .filter(|(name, _)| !name.starts_with("__"))
+            .map(|(name, &loc)| (name.as_str(), loc))
+            .collect();
+        vars.sort_by_key(|&(_, loc)| loc);
+        vars
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Hands out numbers used to build jump labels, so that every conditional in a
+// program gets its own `else_N`/`endif_N` pair.
+pub struct LabelAllocator {
+    next_id: usize,
+}
+
+impl LabelAllocator {
+    pub fn new() -> Self {
+        LabelAllocator { next_id: 0 }
+    }
+
+    pub fn fresh(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+impl Default for LabelAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// State for a single compilation. The parser fills it in and code generation
+// reads from it, so every run starts from a clean memory map.
+pub struct CompilationContext {
+    pub symbols: SymbolTable,
+    pub labels: LabelAllocator,
+    pub runtime: RuntimeLibrary,
+    // Set to record which IR instruction each emitted line came from.
+    pub trace: Option<CodegenTrace>,
+}
+
+impl CompilationContext {
+    pub fn new() -> Self {
+        CompilationContext {
+            symbols: SymbolTable::new(),
+            labels: LabelAllocator::new(),
+            runtime: RuntimeLibrary::new(),
+            trace: None,
+        }
+    }
+}
+
+impl Default for CompilationContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Fails if the assembly listing defines the same label more than once.
+pub fn check_unique_labels(asm: &str) -> io::Result<()> {
+    let mut seen = HashSet::new();
+    for line in asm.lines() {
+        if let Some(label) = line.trim().strip_suffix(':') {
+            if !seen.insert(label) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("label '{}' defined more than once", label),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declare_allocates_in_order() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.declare("x").unwrap(), 1);
+        assert_eq!(symbols.declare("y").unwrap(), 2);
+        assert_eq!(symbols.declare("x").unwrap(), 1);
+        assert_eq!(symbols.declare("y").unwrap(), 2);
+        assert!(!symbols.is_declared("z"));
+        symbols.declare("__mul_x").unwrap();
+        assert_eq!(symbols.variables(), vec![("x", 1), ("y", 2)]);
+    }
+
+    #[test]
+    fn test_temps_come_after_variables_and_are_reused() {
+        let mut symbols = SymbolTable::new();
+        symbols.declare("x").unwrap();
+        let t1 = symbols.acquire_temp().unwrap();
+        let t2 = symbols.acquire_temp().unwrap();
+        assert_eq!((t1, t2), (2, 3));
+        symbols.release_temp(t2);
+        assert_eq!(symbols.acquire_temp().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_addresses_stop_at_255() {
+        let mut symbols = SymbolTable::new();
+        for i in 1..MAX_ADDRESS {
+            assert_eq!(symbols.declare(&format!("v{}", i)).unwrap(), i);
+        }
+        assert_eq!(symbols.acquire_temp().unwrap(), 255);
+        let err = symbols.declare("x").unwrap_err();
+        assert_eq!(err.to_string(), "no data memory left for 'x': all 255 cells are in use");
+        assert!(symbols.acquire_temp().is_err());
+        // A released cell can still be handed out again.
+        symbols.release_temp(255);
+        assert_eq!(symbols.acquire_temp().unwrap(), 255);
+    }
+
+    #[test]
+    fn test_contexts_are_independent() {
+        let mut first = CompilationContext::new();
+        first.symbols.declare("x").unwrap();
+        let second = CompilationContext::new();
+        assert!(first.symbols.is_declared("x"));
+        assert!(!second.symbols.is_declared("x"));
+    }
+
+    #[test]
+    fn test_labels_are_fresh() {
+        let mut labels = LabelAllocator::new();
+        assert_eq!(labels.fresh(), 0);
+        assert_eq!(labels.fresh(), 1);
+    }
+
+    #[test]
+    fn test_check_unique_labels() {
+        assert!(check_unique_labels("jmp %endif_0\nelse_0:\nendif_0:\n").is_ok());
+        assert!(check_unique_labels("endif_0:\nendif_0:\n").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// Half-open byte range `start..end` into the source text, together with the
+// 1-based line and column where it starts.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span { start, end, line, column }
+    }
+
+    // Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        let first = if other.start < self.start { other } else { self };
+        Span { end: self.end.max(other.end), ..first }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, message: message.into(), span }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, message: message.into(), span }
+    }
+
+    // Formats the diagnostic with the offending source line and a caret
+    // underline beneath the span.
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let (line, col) = (self.span.line.max(1), self.span.column.max(1));
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let width = self.span.end.saturating_sub(self.span.start).max(1);
+        let width = width.min(text.len().saturating_sub(col - 1).max(1));
+        let gutter = " ".repeat(line.to_string().len());
+
+        let mut out = String::new();
+        out.push_str(&format!("{}: {}\n", self.severity, self.message));
+        out.push_str(&format!("{}--> {}:{}:{}\n", gutter, filename, line, col));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line, text));
+        out.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(col - 1), "^".repeat(width)));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_to() {
+        let left = Span::new(8, 9, 2, 9);
+        let right = Span::new(12, 14, 2, 13);
+        assert_eq!(left.to(right), Span::new(8, 14, 2, 9));
+        assert_eq!(right.to(left), Span::new(8, 14, 2, 9));
+    }
+
+    #[test]
+    fn test_render_underlines_span() {
+        let source = "int x = 1;\nint yy = 2\n";
+        let diag = Diagnostic::error("Expected ';'", Span::new(15, 17, 2, 5));
+        let rendered = diag.render("input.txt", source);
+        assert_eq!(
+            rendered,
+            "error: Expected ';'\n --> input.txt:2:5\n  |\n2 | int yy = 2\n  |     ^^\n"
+        );
+    }
+}
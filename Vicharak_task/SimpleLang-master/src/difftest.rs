@@ -0,0 +1,135 @@
+use std::fmt;
+use std::fs;
+use crate::assembler::Program;
+use crate::interp;
+use crate::sim::Cpu;
+use crate::{CompileOptions, MAX_OPT_LEVEL};
+
+// Differential testing of the code generator. Each program is evaluated
+// straight from its AST by the reference interpreter, then compiled and run on
+// the CPU model; every variable must end up with the same value both ways.
+// Every program is checked at each optimization level.
+
+const SIM_STEP_LIMIT: usize = 10_000_000;
+
+// Why a program failed the check, with the listing that was run.
+pub struct Divergence {
+    pub message: String,
+    pub asm: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n--- generated assembly ---\n{}", self.message, self.asm)
+    }
+}
+
+// The first variable whose simulated value differs from the interpreter's.
+// Variables declared in code that never ran hold 0 on both sides.
+fn first_difference(expected: &interp::Environment, actual: &[(&str, u8)]) -> Option<String> {
+    actual.iter().find(|&&(name, val)| expected.get(name) != val).map(|&(name, val)| {
+        format!("variable '{}': interpreter gives {}, simulator gives {}", name, expected.get(name), val)
+    })
+}
+
+// Checks `source` compiled at `opt_level` against the interpreter, which
+// always runs the program as written.
+pub fn check(source: &str, opt_level: u8) -> Result<(), Divergence> {
+    let fail = |message: String, asm: &str| Divergence { message, asm: asm.to_string() };
+    let options = CompileOptions { opt_level, ..CompileOptions::default() };
+    let compiled = crate::compile(source, &options)
+        .map_err(|errors| fail(format!("compilation failed: {}", errors[0].message), ""))?;
+    let asm = compiled.asm;
+    let parsed = crate::parse(source, &options).map_err(|errors| fail(errors[0].message.clone(), &asm))?;
+    let expected = interp::interpret(&parsed.ast, interp::DEFAULT_STEP_LIMIT)
+        .map_err(|err| fail(format!("interpreter failed: {}", err), &asm))?;
+    let program = Program::parse(&asm).map_err(|err| fail(format!("assembly failed: {}", err), &asm))?;
+    let mut cpu = Cpu::new();
+    cpu.run(&program, SIM_STEP_LIMIT)
+        .map_err(|err| fail(format!("simulator failed: {}", err), &asm))?;
+
+    let actual: Vec<(&str, u8)> = compiled
+        .symbols
+        .variables()
+        .into_iter()
+        .map(|(name, loc)| (name, cpu.memory[loc as usize]))
+        .collect();
+    match first_difference(&expected, &actual) {
+        Some(message) => Err(fail(message, &asm)),
+        None => Ok(()),
+    }
+}
+
+// Programs built systematically from every binary operator and a spread of
+// operand values, in both leaf and spilled (nested) operand positions.
+pub fn generated_corpus() -> Vec<String> {
+    const OPS: [&str; 11] = ["+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">="];
+    const VALUES: [u8; 7] = [0, 1, 2, 7, 128, 200, 255];
+    let mut programs = Vec::new();
+    for op in OPS {
+        for x in VALUES {
+            let mut source = format!("int x = {};\n", x);
+            for (i, y) in VALUES.iter().enumerate() {
+                source += &format!("int y{i} = {y};\n");
+                source += &format!("int lit{i} = {x} {op} {y};\n");
+                source += &format!("int var{i} = x {op} y{i};\n");
+                source += &format!("int nest{i} = (x - 1) {op} (y{i} + x);\n");
+                source += &format!("int cond{i} = 0;\nif (x {op} y{i}) {{ cond{i} = 1; }} else {{ cond{i} = 2; }}\n");
+            }
+            programs.push(source);
+        }
+    }
+    programs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_agrees(name: &str, source: &str) {
+        for opt_level in 0..=MAX_OPT_LEVEL {
+            if let Err(divergence) = check(source, opt_level) {
+                panic!("{} diverges at -O{}: {}\n--- source ---\n{}", name, opt_level, divergence, source);
+            }
+        }
+    }
+
+    #[test]
+    fn test_input_program() {
+        assert_agrees("input.txt", include_str!("../input.txt"));
+    }
+
+    #[test]
+    fn test_corpus() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                assert_agrees(&path.display().to_string(), &fs::read_to_string(&path).unwrap());
+                count += 1;
+            }
+        }
+        assert!(count > 0, "no programs found in {}", dir);
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        for (i, source) in generated_corpus().iter().enumerate() {
+            assert_agrees(&format!("generated program {}", i), source);
+        }
+    }
+
+    #[test]
+    fn test_reports_first_diverging_variable() {
+        let mut expected = interp::Environment::new(0);
+        expected.set("a", 1);
+        expected.set("b", 2);
+        let actual = [("a", 1), ("b", 3), ("c", 4)];
+        assert_eq!(
+            first_difference(&expected, &actual).unwrap(),
+            "variable 'b': interpreter gives 2, simulator gives 3"
+        );
+        assert!(first_difference(&expected, &actual[..1]).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+use std::mem;
+use crate::ast::{walk_expr_mut, walk_stmt_mut, Expr, Stmt, VisitorMut};
+use crate::diagnostic::Span;
+use crate::interp::{apply_binary, apply_unary};
+
+// Constant folding and algebraic simplification over the syntax tree, run
+// before lowering when `CompileOptions::opt_level` is 1 or more.
+//
+// Operators on constants are evaluated with the interpreter's own 8-bit
+// arithmetic, so folding never changes what a program computes. `x + 0`,
+// `0 + x` and `x - 0` become `x`. An `if` whose condition folds to a constant
+// is replaced by the branch that would run, and a `while` whose condition
+// folds to 0 is dropped.
+
+pub fn fold_constants(ast: &mut Stmt) {
+    Folder.visit_stmt_mut(ast);
+}
+
+struct Folder;
+
+impl VisitorMut for Folder {
+    // Children are folded first, so a node only ever sees folded operands.
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        if let Some(folded) = fold_expr(expr) {
+            *expr = folded;
+        }
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        if let Some(folded) = fold_stmt(stmt) {
+            *stmt = folded;
+        }
+    }
+}
+
+// What `expr` simplifies to, if anything. Operands that survive are moved
+// out, leaving `expr` to be overwritten.
+fn fold_expr(expr: &mut Expr) -> Option<Expr> {
+    match expr {
+        Expr::Binary { l, op, r, span } => match (&**l, op.as_str(), &**r) {
+            (Expr::Number { val: l, .. }, _, Expr::Number { val: r, .. }) => {
+                apply_binary(op, *l, *r).map(|val| Expr::Number { val, span: *span })
+            }
+            (_, "+" | "-", Expr::Number { val: 0, .. }) => Some(take_expr(l)),
+            (Expr::Number { val: 0, .. }, "+", _) => Some(take_expr(r)),
+            _ => None,
+        },
+        Expr::Unary { op, operand, span } => match **operand {
+            Expr::Number { val, .. } => apply_unary(op, val).map(|val| Expr::Number { val, span: *span }),
+            _ => None,
+        },
+        Expr::Number { .. } | Expr::Variable { .. } => None,
+    }
+}
+
+fn fold_stmt(stmt: &mut Stmt) -> Option<Stmt> {
+    match stmt {
+        Stmt::If { cond: Expr::Number { val, .. }, then_branch, else_branch, span } => {
+            if *val != 0 {
+                Some(take_stmt(then_branch))
+            } else {
+                Some(else_branch.as_mut().map_or_else(|| empty_block(*span), take_stmt))
+            }
+        }
+        Stmt::While { cond: Expr::Number { val: 0, .. }, span, .. } => Some(empty_block(*span)),
+        _ => None,
+    }
+}
+
+fn take_expr(expr: &mut Expr) -> Expr {
+    let span = expr.span();
+    mem::replace(expr, Expr::Number { val: 0, span })
+}
+
+fn take_stmt(stmt: &mut Box<Stmt>) -> Stmt {
+    let span = stmt.span();
+    mem::replace(&mut **stmt, empty_block(span))
+}
+
+fn empty_block(span: Span) -> Stmt {
+    Stmt::Block { stmts: Vec::new(), span }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer;
+    use crate::test_support::parse;
+
+    fn fold(source: &str) -> String {
+        let mut ast = parse(source);
+        fold_constants(&mut ast);
+        printer::to_sexpr(&ast)
+    }
+
+    #[test]
+    fn test_folds_with_8_bit_wrapping() {
+        assert_eq!(fold("int y = 5 + 3;"), fold("int y = 8;"));
+        assert_eq!(fold("int y = 200 + 100 * 1;"), fold("int y = 44;"));
+        assert_eq!(fold("int y = -(2 - 1) + (7 > 3);"), fold("int y = 0;"));
+        assert_eq!(fold("int y = 9 / 0;"), fold("int y = 255;"));
+    }
+
+    #[test]
+    fn test_adding_zero_is_dropped() {
+        assert_eq!(fold("int x; int y = (x + 0) - (1 - 1);"), fold("int x; int y = x;"));
+        assert_eq!(fold("int x; int y = 0 + x * 2;"), fold("int x; int y = x * 2;"));
+        // `0 - x` is a negation, not an identity.
+        assert!(fold("int x; int y = 0 - x;").contains("(-\n      (num 0)\n      (var x))"));
+    }
+
+    #[test]
+    fn test_constant_conditions_keep_one_branch() {
+        let then_kept = "(block\n  (declare a)\n  (block\n    (assign a\n      (num 1))))\n";
+        let else_kept = then_kept.replace("num 1", "num 2");
+        let dropped = "(block\n  (declare a)\n  (block))\n";
+        assert_eq!(fold("int a; if (1 == 1) { a = 1; } else { a = 2; }"), then_kept);
+        assert_eq!(fold("int a; if (2 < 1) { a = 1; } else { a = 2; }"), else_kept);
+        assert_eq!(fold("int a; if (0) { a = 1; }"), dropped);
+        assert_eq!(fold("int a; while (3 - 3) { a = 1; }"), dropped);
+        // Conditions on variables stay.
+        assert_eq!(fold("int a; if (a == 1 + 0) { a = 2; }"), fold("int a; if (a == 1) { a = 2; }"));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::ast::{Expr, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Lexer, Trivia, TriviaKind};
+use crate::parser::{self, Parser};
+
+// Canonical source formatting. The program is parsed and printed back from
+// its syntax tree, so the output always parses to the same tree:
+//
+// - one statement per line, indented four spaces per block level;
+// - `if`/`else` and `while` bodies always in braces, `else if` kept flat;
+// - single spaces around binary operators and after keywords;
+// - only the parentheses that precedence requires;
+// - a run of blank lines between statements becomes one blank line, and
+//   blank lines at the start or end of a block are dropped;
+// - a comment on the same line after a statement or an opening brace stays
+//   there, two spaces after it; any other comment goes on its own line
+//   before the next statement, or before the closing brace of its block.
+//
+// Anything the tree does not hold, such as blank lines and comments, comes
+// from the lexer's trivia.
+
+const INDENT: &str = "    ";
+
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut lexer = Lexer::with_trivia(source.to_string());
+    let tokens = lexer.tokenize()?;
+    let trivia = lexer.take_trivia();
+    let ast = Parser::new(tokens).parse()?;
+
+    let comments = trivia.iter().filter(|t| t.kind != TriviaKind::BlankLine).collect();
+    let mut formatter = Formatter { out: String::new(), source, trivia: &trivia, comments, next_comment: 0, last_end: None };
+    match &ast {
+        Stmt::Block { stmts, .. } => formatter.stmts(stmts, 0, usize::MAX),
+        stmt => formatter.stmts(std::slice::from_ref(stmt), 0, usize::MAX),
+    }
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    out: String,
+    source: &'a str,
+    trivia: &'a [Trivia],
+    // Comments in source order; those before `next_comment` are printed.
+    comments: Vec<&'a Trivia>,
+    next_comment: usize,
+    // Where the last statement or comment printed in the current block
+    // ended; `None` at the start of a block.
+    last_end: Option<usize>,
+}
+
+impl Formatter<'_> {
+    fn indent(&mut self, depth: usize) {
+        self.out.push_str(&INDENT.repeat(depth));
+    }
+
+    fn blank_line_between(&self, start: usize, end: usize) -> bool {
+        self.trivia
+            .iter()
+            .any(|t| t.kind == TriviaKind::BlankLine && t.span.start >= start && t.span.end <= end)
+    }
+
+    // Keeps one blank line before an item at `start` if the source had any.
+    fn separate(&mut self, start: usize) {
+        if self.last_end.is_some_and(|end| self.blank_line_between(end, start)) {
+            self.out.push('\n');
+        }
+    }
+
+    // Prints the comments that start before `pos`, each on its own line.
+    fn leading_comments(&mut self, pos: usize, depth: usize) {
+        while let Some(&comment) = self.comments.get(self.next_comment) {
+            if comment.span.start >= pos {
+                break;
+            }
+            self.separate(comment.span.start);
+            self.indent(depth);
+            self.out += &comment.text;
+            self.out.push('\n');
+            self.last_end = Some(comment.span.end);
+            self.next_comment += 1;
+        }
+    }
+
+    // Appends the comment that follows `pos` on the same source line, if any.
+    fn trailing_comment(&mut self, pos: usize) {
+        let Some(&comment) = self.comments.get(self.next_comment) else { return };
+        if comment.span.start >= pos && !self.source[pos..comment.span.start].contains('\n') {
+            self.out += "  ";
+            self.out += &comment.text;
+            self.last_end = Some(comment.span.end);
+            self.next_comment += 1;
+        }
+    }
+
+    // The statements of a block whose closing brace is at `end`.
+    fn stmts(&mut self, stmts: &[Stmt], depth: usize, end: usize) {
+        self.last_end = None;
+        for stmt in stmts {
+            self.leading_comments(stmt.span().start, depth);
+            self.separate(stmt.span().start);
+            self.stmt(stmt, depth);
+        }
+        self.leading_comments(end, depth);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, depth: usize) {
+        self.indent(depth);
+        match stmt {
+            Stmt::Declare { name, val: Some(val), .. } => self.out += &format!("int {} = {};", name, expr(self.source, val)),
+            Stmt::Declare { name, val: None, .. } => self.out += &format!("int {};", name),
+            Stmt::Assign { name, val, .. } => self.out += &format!("{} = {};", name, expr(self.source, val)),
+            Stmt::If { .. } => self.if_chain(stmt, depth),
+            Stmt::While { cond, body, .. } => {
+                self.out += &format!("while ({}) ", expr(self.source, cond));
+                self.body(body, depth);
+            }
+            Stmt::Block { .. } => self.body(stmt, depth),
+            Stmt::Expr(e) => self.out += &format!("{};", expr(self.source, e)),
+        }
+        self.last_end = Some(stmt.span().end);
+        self.trailing_comment(stmt.span().end);
+        self.out.push('\n');
+    }
+
+    // An `if` and its `else` branches, starting at the current column.
+    fn if_chain(&mut self, stmt: &Stmt, depth: usize) {
+        let Stmt::If { cond, then_branch, else_branch, .. } = stmt else {
+            return self.body(stmt, depth);
+        };
+        self.out += &format!("if ({}) ", expr(self.source, cond));
+        self.body(then_branch, depth);
+        if let Some(else_branch) = else_branch {
+            self.out += " else ";
+            self.if_chain(else_branch, depth);
+        }
+    }
+
+    // A braced body; a lone statement gets braces added.
+    fn body(&mut self, stmt: &Stmt, depth: usize) {
+        self.out.push('{');
+        match stmt {
+            Stmt::Block { stmts, span } => {
+                self.trailing_comment(span.start + 1);
+                self.out.push('\n');
+                self.stmts(stmts, depth + 1, span.end - 1);
+            }
+            stmt => {
+                self.out.push('\n');
+                self.stmts(std::slice::from_ref(stmt), depth + 1, stmt.span().end);
+            }
+        }
+        self.indent(depth);
+        self.out.push('}');
+    }
+}
+
+// How tightly `e` holds together when it appears as an operand.
+fn precedence(e: &Expr) -> u8 {
+    match e {
+        Expr::Binary { op, .. } => parser::binding_power(op).unwrap_or(0),
+        Expr::Unary { .. } => parser::UNARY,
+        Expr::Number { .. } | Expr::Variable { .. } => u8::MAX,
+    }
+}
+
+// Number literals are copied from the source, so that `0007` or an
+// out-of-range `300` comes back as written rather than as the wrapped value.
+fn expr(source: &str, e: &Expr) -> String {
+    match e {
+        Expr::Number { span, .. } => source[span.start..span.end].to_string(),
+        Expr::Variable { name, .. } => name.clone(),
+        Expr::Binary { l, op, r, .. } => {
+            // Operators associate to the left, so a right operand of the same
+            // precedence needs parentheses: `a - (b - c)`.
+            let bp = precedence(e);
+            let l = operand(source, l, precedence(l) < bp);
+            let r = operand(source, r, precedence(r) <= bp);
+            format!("{} {} {}", l, op, r)
+        }
+        // `-(-x)` rather than `--x`.
+        Expr::Unary { op, operand: inner, .. } => {
+            format!("{}{}", op, operand(source, inner, precedence(inner) <= parser::UNARY))
+        }
+    }
+}
+
+fn operand(source: &str, e: &Expr, parenthesize: bool) -> String {
+    if parenthesize {
+        format!("({})", expr(source, e))
+    } else {
+        expr(source, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::{generate_program, Rng};
+    use crate::printer::to_sexpr;
+    use crate::test_support::parse;
+
+    fn format(source: &str) -> String {
+        format_source(source).unwrap()
+    }
+
+    fn sexpr(source: &str) -> String {
+        to_sexpr(&parse(source))
+    }
+
+    #[test]
+    fn test_formats_input_program() {
+        let expected = "int x = 42;\nint y = x + 5;\nif (x == y) {\n    y = y + 1;\n}\n";
+        assert_eq!(format(include_str!("../input.txt")), expected);
+    }
+
+    #[test]
+    fn test_else_if_chains_and_loops() {
+        let source = "int a;if(a<1)a=1;else if(a>2){a=2;}else{while(a){a=a-1;}}";
+        let expected = "\
+int a;
+if (a < 1) {
+    a = 1;
+} else if (a > 2) {
+    a = 2;
+} else {
+    while (a) {
+        a = a - 1;
+    }
+}
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_keeps_only_needed_parentheses() {
+        let source = "int a = 1; a = ((a * 2) + 3); a = a - (a - 1); a = (a + 1) * -(a - 1); a = -(-a); a = (a < 1) == 0;";
+        let expected = "int a = 1;\na = a * 2 + 3;\na = a - (a - 1);\na = (a + 1) * -(a - 1);\na = -(-a);\na = a < 1 == 0;\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_number_literals_are_kept_as_written() {
+        // 300 wraps to 44 and 0007 is 7, but the formatter must not say so.
+        assert_eq!(format("int x = 300; int y = 0007 + -0;"), "int x = 300;\nint y = 0007 + -0;\n");
+    }
+
+    #[test]
+    fn test_blank_lines_between_statements() {
+        let source = "int a = 1;\n\n\n\nint b = 2;\nif (a) {\n\n  a = 2;\n\n  b = 3;\n\n}\n";
+        let expected = "int a = 1;\n\nint b = 2;\nif (a) {\n    a = 2;\n\n    b = 3;\n}\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_round_trips_and_is_idempotent() {
+        let mut rng = Rng::new(4);
+        for _ in 0..200 {
+            let source = generate_program(&mut rng);
+            let formatted = format(&source);
+            assert_eq!(sexpr(&formatted), sexpr(&source), "{}", source);
+            assert_eq!(format(&formatted), formatted);
+        }
+    }
+
+    #[test]
+    fn test_comments_are_preserved() {
+        let source = "\
+// Counts down.
+int a = 3;   // start
+
+
+/* the loop
+   itself */
+while (a) { // until zero
+  a = a - 1;
+  // nothing else
+}
+/* trailing */
+";
+        let expected = "\
+// Counts down.
+int a = 3;  // start
+
+/* the loop
+   itself */
+while (a) {  // until zero
+    a = a - 1;
+    // nothing else
+}
+/* trailing */
+";
+        let formatted = format(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_comments_inside_statements_are_not_lost() {
+        let formatted = format("int a = 1 + /* two */ 2;\nif (a) /* here */ { a = 0; }");
+        assert_eq!(formatted, "int a = 1 + 2;\n/* two */\nif (a) {\n    /* here */\n    a = 0;\n}\n");
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_only_comments() {
+        assert_eq!(format("  // just a note\n"), "// just a note\n");
+    }
+
+    #[test]
+    fn test_syntax_errors_are_returned() {
+        let errors = format_source("int x = ;").unwrap_err();
+        assert_eq!(errors[0].message, "Expected expression. Found ';'");
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+use std::panic;
+use crate::assembler::{self, Program};
+use crate::{CompileOptions, MAX_OPT_LEVEL};
+
+// Random testing of the lexer -> parser -> code generator pipeline.
+//
+// Inputs come from two places: a grammar-aware generator that only writes
+// valid SimpleLang programs, and byte-level mutations of those programs that
+// mostly are not. Any input may be rejected with diagnostics; the pipeline
+// fails only if it panics or emits assembly that the assembler rejects. A
+// failing input is shrunk to a minimal reproducer before it is reported.
+
+// Small xorshift generator, so runs are reproducible from a seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Rng { state: (seed ^ 0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // A value in 0..n.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const OPERATORS: [&str; 11] = ["+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">="];
+const INTERESTING: [u8; 8] = [0, 1, 2, 127, 128, 254, 255, 7];
+// Keeps every variable, temporary and runtime cell inside data memory.
+const MAX_VARIABLES: usize = 40;
+const MAX_BLOCK_DEPTH: usize = 3;
+const MAX_EXPR_DEPTH: usize = 4;
+
+// Writes random programs that lex, parse and terminate. Variables are only
+// used after they are declared, and every loop counts a counter of its own up
+// to a small bound; the body may read the counter but never assigns it.
+pub struct ProgramGenerator<'a> {
+    rng: &'a mut Rng,
+    // Variables that statements may assign.
+    assignable: Vec<String>,
+    // Variables that expressions may read.
+    readable: Vec<String>,
+    next_var: usize,
+    out: String,
+}
+
+impl<'a> ProgramGenerator<'a> {
+    pub fn new(rng: &'a mut Rng) -> Self {
+        ProgramGenerator { rng, assignable: Vec::new(), readable: Vec::new(), next_var: 0, out: String::new() }
+    }
+
+    pub fn generate(mut self) -> String {
+        let count = 1 + self.rng.below(12);
+        for _ in 0..count {
+            self.gen_stat(0);
+        }
+        self.out
+    }
+
+    fn indent(&mut self, depth: usize) {
+        self.out.push_str(&"    ".repeat(depth));
+    }
+
+    fn fresh_var(&mut self, prefix: &str) -> String {
+        let name = format!("{}{}", prefix, self.next_var);
+        self.next_var += 1;
+        name
+    }
+
+    fn gen_stat(&mut self, depth: usize) {
+        let can_declare = self.next_var < MAX_VARIABLES;
+        let can_nest = depth < MAX_BLOCK_DEPTH;
+        match self.rng.below(10) {
+            0..=2 if can_declare => self.gen_declaration(depth),
+            3..=5 if !self.assignable.is_empty() => {
+                let name = self.rng.pick(&self.assignable).clone();
+                let val = self.gen_exp(0);
+                self.indent(depth);
+                self.out += &format!("{} = {};\n", name, val);
+            }
+            6 | 7 if can_nest => self.gen_if(depth),
+            8 if can_nest && can_declare => self.gen_while(depth),
+            _ if can_declare => self.gen_declaration(depth),
+            _ => {
+                // Out of variables: an empty-bodied `if` still exercises
+                // conditions and labels.
+                let cond = self.gen_exp(0);
+                self.indent(depth);
+                self.out += &format!("if ({}) {{\n", cond);
+                self.indent(depth);
+                self.out += "}\n";
+            }
+        }
+    }
+
+    fn gen_declaration(&mut self, depth: usize) {
+        let name = self.fresh_var("v");
+        self.indent(depth);
+        if self.rng.chance(15) {
+            self.out += &format!("int {};\n", name);
+        } else {
+            let val = self.gen_exp(0);
+            self.out += &format!("int {} = {};\n", name, val);
+        }
+        self.assignable.push(name.clone());
+        self.readable.push(name);
+    }
+
+    fn gen_block(&mut self, depth: usize) {
+        let count = 1 + self.rng.below(4);
+        for _ in 0..count {
+            self.gen_stat(depth + 1);
+        }
+    }
+
+    fn gen_if(&mut self, depth: usize) {
+        let cond = self.gen_exp(0);
+        self.indent(depth);
+        self.out += &format!("if ({}) {{\n", cond);
+        self.gen_block(depth);
+        self.indent(depth);
+        if self.rng.chance(50) {
+            self.out += "} else {\n";
+            self.gen_block(depth);
+            self.indent(depth);
+        }
+        self.out += "}\n";
+    }
+
+    fn gen_while(&mut self, depth: usize) {
+        let counter = self.fresh_var("c");
+        let bound = 1 + self.rng.below(5);
+        self.indent(depth);
+        self.out += &format!("int {} = 0;\n", counter);
+        self.indent(depth);
+        self.out += &format!("while ({} < {}) {{\n", counter, bound);
+        self.readable.push(counter.clone());
+        self.gen_block(depth);
+        self.indent(depth + 1);
+        self.out += &format!("{} = {} + 1;\n", counter, counter);
+        self.indent(depth);
+        self.out += "}\n";
+    }
+
+    fn gen_exp(&mut self, depth: usize) -> String {
+        if depth >= MAX_EXPR_DEPTH || self.rng.chance(35) {
+            return self.gen_leaf();
+        }
+        match self.rng.below(8) {
+            0 => format!("-{}", self.gen_leaf()),
+            1 => format!("({})", self.gen_exp(depth + 1)),
+            _ => {
+                let l = self.gen_exp(depth + 1);
+                let op = *self.rng.pick(&OPERATORS);
+                let r = self.gen_exp(depth + 1);
+                if self.rng.chance(50) {
+                    format!("({} {} {})", l, op, r)
+                } else {
+                    format!("{} {} {}", l, op, r)
+                }
+            }
+        }
+    }
+
+    fn gen_leaf(&mut self) -> String {
+        if !self.readable.is_empty() && self.rng.chance(50) {
+            return self.rng.pick(&self.readable).clone();
+        }
+        if self.rng.chance(50) {
+            self.rng.pick(&INTERESTING).to_string()
+        } else {
+            self.rng.below(256).to_string()
+        }
+    }
+}
+
+pub fn generate_program(rng: &mut Rng) -> String {
+    ProgramGenerator::new(rng).generate()
+}
+
+// A failing input, already minimized.
+pub struct Failure {
+    pub message: String,
+    pub input: Vec<u8>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n--- minimized input ---\n{}", self.message, String::from_utf8_lossy(&self.input))
+    }
+}
+
+// Compiles `source` at every optimization level and assembles the result.
+// Diagnostics are an acceptable outcome, as is a program too large for the
+// ROM; assembly errors tied to a line of the listing mean the code generator
+// emitted something invalid. The ROM image is built here rather than by
+// `compile`, to tell those two kinds of assembly error apart.
+fn compile_and_assemble(source: &str) -> Result<(), String> {
+    for opt_level in 0..=MAX_OPT_LEVEL {
+        let options = CompileOptions { opt_level, ..CompileOptions::default() };
+        let program = match crate::compile(source, &options) {
+            Ok(program) => program,
+            Err(_) if crate::parse(source, &options).is_err() => return Ok(()),
+            Err(errors) => return Err(format!("code generation failed at -O{}: {}", opt_level, errors[0].message)),
+        };
+        let program = Program::parse(&program.asm)
+            .map_err(|err| format!("emitted assembly does not assemble at -O{}: {}", opt_level, err))?;
+        if let Err(err) = assembler::assemble(&program) {
+            if err.line.is_some() {
+                return Err(format!("emitted assembly does not assemble at -O{}: {}", opt_level, err));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The fuzz entry point: runs arbitrary bytes through the pipeline.
+pub fn check_input(data: &[u8]) -> Result<(), String> {
+    let source = String::from_utf8_lossy(data);
+    match panic::catch_unwind(|| compile_and_assemble(&source)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("panic: {}", message))
+        }
+    }
+}
+
+// Whether two failure messages describe the same kind of failure, so that
+// minimizing does not wander off to a different bug.
+fn same_kind(a: &str, b: &str) -> bool {
+    a.split(':').next() == b.split(':').next()
+}
+
+// Shrinks `data` while `fails` keeps holding, by deleting ever smaller chunks.
+pub fn minimize(data: &[u8], fails: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut current = data.to_vec();
+    let mut chunk = current.len().max(1);
+    while chunk > 0 {
+        let mut start = 0;
+        let mut shrunk = false;
+        while start < current.len() {
+            let end = (start + chunk).min(current.len());
+            let mut candidate = current[..start].to_vec();
+            candidate.extend_from_slice(&current[end..]);
+            if fails(&candidate) {
+                current = candidate;
+                shrunk = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if !shrunk {
+            chunk /= 2;
+        }
+    }
+    current
+}
+
+const TOKENS: [&str; 19] = [
+    "int ", "if", "else", "while", "(", ")", "{", "}", ";", "=", "==", "-", "*", "999", "x", "\n", "//", "/*", "*/",
+];
+
+// A byte-level variation of `data`: flips, insertions of bytes and tokens,
+// deletions and duplicated slices.
+pub fn mutate(rng: &mut Rng, data: &[u8]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        let pos = rng.below(out.len() + 1);
+        match rng.below(5) {
+            0 if pos < out.len() => out[pos] ^= 1 << rng.below(8),
+            1 => out.insert(pos, rng.below(256) as u8),
+            2 => {
+                let token = rng.pick(&TOKENS).as_bytes();
+                out.splice(pos..pos, token.iter().copied());
+            }
+            3 if pos < out.len() => {
+                let end = (pos + 1 + rng.below(8)).min(out.len());
+                out.drain(pos..end);
+            }
+            _ if !out.is_empty() => {
+                let from = rng.below(out.len());
+                let end = (from + 1 + rng.below(16)).min(out.len());
+                let slice = out[from..end].to_vec();
+                out.splice(pos..pos, slice);
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// Runs `iterations` generated programs, each followed by a few mutations of
+// it. Returns the first failure, minimized.
+pub fn fuzz(seed: u64, iterations: usize) -> Result<(), Failure> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..iterations {
+        let program = generate_program(&mut rng).into_bytes();
+        let mut inputs = vec![program.clone()];
+        for _ in 0..4 {
+            inputs.push(mutate(&mut rng, &program));
+        }
+        for input in inputs {
+            if let Err(message) = check_input(&input) {
+                let input = minimize(&input, |candidate| {
+                    check_input(candidate).err().is_some_and(|m| same_kind(&m, &message))
+                });
+                return Err(Failure { message, input });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftest;
+
+    #[test]
+    fn test_generated_programs_are_valid() {
+        let mut rng = Rng::new(1);
+        for _ in 0..200 {
+            let source = generate_program(&mut rng);
+            let program = crate::parse(&source, &CompileOptions::default())
+                .unwrap_or_else(|errors| panic!("{}\n--- source ---\n{}", errors[0].message, source));
+            assert!(program.warnings.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_generated_programs_agree_with_interpreter() {
+        let mut rng = Rng::new(2);
+        for _ in 0..200 {
+            let source = generate_program(&mut rng);
+            for opt_level in 0..=MAX_OPT_LEVEL {
+                if let Err(divergence) = difftest::check(&source, opt_level) {
+                    panic!("-O{}: {}\n--- source ---\n{}", opt_level, divergence, source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_input_is_not_a_failure() {
+        for input in [&b"int x = ;"[..], b"}}}((", b"\xff\xfe int", b"x = 1;", b""] {
+            assert_eq!(check_input(input), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_fuzz_finds_nothing() {
+        if let Err(failure) = fuzz(3, 100) {
+            panic!("{}", failure);
+        }
+    }
+
+    #[test]
+    fn test_minimize_keeps_the_failure() {
+        let input = b"int a = 1;\nint b = 2;\nint c = 3;\n";
+        let minimized = minimize(input, |data| data.windows(5).any(|w| w == b"b = 2"));
+        assert_eq!(minimized, b"b = 2");
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::ast::{Expr, Stmt};
+
+// Reference semantics for SimpleLang, evaluated straight from the AST. Every
+// value is an unsigned byte and arithmetic wraps exactly as it does on the
+// 8-bit CPU, so the final variables can be compared against a run of the
+// generated code.
+
+// The step limit `eval` and the differential tests pass to `interpret`. Each
+// statement and each loop iteration is one step.
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct EvalError {
+    pub message: String,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Variable values, remembered in declaration order.
+pub struct Environment {
+    values: HashMap<String, u8>,
+    order: Vec<String>,
+    steps_left: usize,
+}
+
+impl Environment {
+    pub fn new(step_limit: usize) -> Self {
+        Environment { values: HashMap::new(), order: Vec::new(), steps_left: step_limit }
+    }
+
+    pub fn get(&self, name: &str) -> u8 {
+        self.values.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, name: &str, val: u8) {
+        if self.values.insert(name.to_string(), val).is_none() {
+            self.order.push(name.to_string());
+        }
+    }
+
+    // Final value of every variable, in declaration order.
+    pub fn variables(&self) -> Vec<(&str, u8)> {
+        self.order.iter().map(|name| (name.as_str(), self.values[name])).collect()
+    }
+
+    // Charges one step against the budget.
+    pub fn tick(&mut self) -> Result<(), EvalError> {
+        if self.steps_left == 0 {
+            return Err(EvalError { message: "program did not finish within the step limit".to_string() });
+        }
+        self.steps_left -= 1;
+        Ok(())
+    }
+}
+
+// The value of `l op r`. Division by zero gives 255 and `x % 0` gives `x`,
+// matching the runtime library; comparisons give 1 or 0.
+pub fn apply_binary(op: &str, l: u8, r: u8) -> Option<u8> {
+    let val = match op {
+        "+" => l.wrapping_add(r),
+        "-" => l.wrapping_sub(r),
+        "*" => l.wrapping_mul(r),
+        "/" => l.checked_div(r).unwrap_or(255),
+        "%" => l.checked_rem(r).unwrap_or(l),
+        "==" => (l == r) as u8,
+        "!=" => (l != r) as u8,
+        "<" => (l < r) as u8,
+        "<=" => (l <= r) as u8,
+        ">" => (l > r) as u8,
+        ">=" => (l >= r) as u8,
+        _ => return None,
+    };
+    Some(val)
+}
+
+impl Expr {
+    pub fn evaluate(&self, env: &mut Environment) -> Result<u8, EvalError> {
+        match self {
+            Expr::Number { val, .. } => Ok(*val),
+            Expr::Variable { name, .. } => Ok(env.get(name)),
+            Expr::Binary { l, op, r, .. } => {
+                let l = l.evaluate(env)?;
+                let r = r.evaluate(env)?;
+                apply_binary(op, l, r).ok_or_else(|| EvalError { message: format!("unsupported binary op '{}'", op) })
+            }
+            Expr::Unary { op, operand, .. } => {
+                let val = operand.evaluate(env)?;
+                apply_unary(op, val).ok_or_else(|| EvalError { message: format!("unsupported unary op '{}'", op) })
+            }
+        }
+    }
+}
+
+impl Stmt {
+    // Runs the statement, charging one step for it and one for every loop
+    // iteration.
+    pub fn evaluate(&self, env: &mut Environment) -> Result<(), EvalError> {
+        match self {
+            Stmt::Declare { name, val, .. } => {
+                env.tick()?;
+                let val = match val {
+                    Some(val) => val.evaluate(env)?,
+                    None => 0,
+                };
+                env.set(name, val);
+            }
+            Stmt::Assign { name, val, .. } => {
+                env.tick()?;
+                let val = val.evaluate(env)?;
+                env.set(name, val);
+            }
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                env.tick()?;
+                if cond.evaluate(env)? != 0 {
+                    then_branch.evaluate(env)?;
+                } else if let Some(else_branch) = else_branch {
+                    else_branch.evaluate(env)?;
+                }
+            }
+            Stmt::While { cond, body, .. } => {
+                env.tick()?;
+                while cond.evaluate(env)? != 0 {
+                    env.tick()?;
+                    body.evaluate(env)?;
+                }
+            }
+            Stmt::Block { stmts, .. } => {
+                for stmt in stmts {
+                    stmt.evaluate(env)?;
+                }
+            }
+            Stmt::Expr(expr) => {
+                expr.evaluate(env)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The value of `op val`.
+pub fn apply_unary(op: &str, val: u8) -> Option<u8> {
+    match op {
+        "-" => Some(val.wrapping_neg()),
+        _ => None,
+    }
+}
+
+// Runs a whole program and returns its final variables, or an error once it
+// has taken `step_limit` steps.
+pub fn interpret(ast: &Stmt, step_limit: usize) -> Result<Environment, EvalError> {
+    let mut env = Environment::new(step_limit);
+    ast.evaluate(&mut env)?;
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
+
+    fn eval(source: &str) -> Result<Vec<(String, u8)>, EvalError> {
+        let env = interpret(&parse(source), 10_000)?;
+        Ok(env.variables().into_iter().map(|(name, val)| (name.to_string(), val)).collect())
+    }
+
+    fn vars(pairs: &[(&str, u8)]) -> Vec<(String, u8)> {
+        pairs.iter().map(|&(name, val)| (name.to_string(), val)).collect()
+    }
+
+    #[test]
+    fn test_input_program() {
+        let source = "int x = 42;\nint y = x + 5;\nif (x == y) {\n    y = y + 1;\n}\n";
+        assert_eq!(eval(source).unwrap(), vars(&[("x", 42), ("y", 47)]));
+    }
+
+    #[test]
+    fn test_wrapping_arithmetic() {
+        let source = "int a = 200 + 100; int b = 3 - 5; int c = 16 * 17; int d = -1;";
+        assert_eq!(eval(source).unwrap(), vars(&[("a", 44), ("b", 254), ("c", 16), ("d", 255)]));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let source = "int a = 7 / 0; int b = 7 % 0; int c = 7 / 2; int d = 7 % 2;";
+        assert_eq!(eval(source).unwrap(), vars(&[("a", 255), ("b", 7), ("c", 3), ("d", 1)]));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "int i = 0; int odd = 0; int even = 0;
+                      while (i < 9) {
+                          if (i % 2 == 1) { odd = odd + 1; } else { even = even + 1; }
+                          i = i + 1;
+                      }";
+        assert_eq!(eval(source).unwrap(), vars(&[("i", 9), ("odd", 4), ("even", 5)]));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let err = eval("int i = 1; while (i) { i = 1; }").unwrap_err();
+        assert_eq!(err.message, "program did not finish within the step limit");
+    }
+}
@@ -0,0 +1,419 @@
+use std::fmt;
+use crate::ast::{Expr, Stmt};
+use crate::context::CompilationContext;
+use crate::diagnostic::Span;
+
+// Three-address intermediate representation, between the syntax tree and
+// instruction selection (`isel.rs`).
+//
+// A program is a list of basic blocks in layout order. Each block is a label,
+// straight-line instructions of the form `dst = src` or `dst = l op r`, and a
+// terminator that jumps, branches on a comparison or halts. Values that have
+// no name in the source live in temporaries `%tN`. Every temporary is
+// assigned once and read once, by a later instruction in the same block.
+//
+// Comparisons come in four forms only, `==`, `!=`, `<` and `>=`, which the CPU
+// tests directly; lowering rewrites `a > b` as `b < a` and `a <= b` as
+// `b >= a`.
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operand {
+    Const(u8),
+    Var(String),
+    Temp(usize),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Inst {
+    // `dst = src`. `dst` is never a constant.
+    Copy { dst: Operand, src: Operand, span: Span },
+    // `dst = l op r`. Comparisons give 1 when they hold and 0 otherwise.
+    Binary { dst: Operand, op: String, l: Operand, r: Operand, span: Span },
+}
+
+impl Inst {
+    // Source range of the AST node the instruction was lowered from.
+    pub fn span(&self) -> Span {
+        match self {
+            Inst::Copy { span, .. } | Inst::Binary { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Terminator {
+    Jump(String),
+    // Goes to `then_label` when `l op r` holds and to `else_label` otherwise.
+    Branch { l: Operand, op: String, r: Operand, then_label: String, else_label: String, span: Span },
+    Halt,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Block {
+    pub label: String,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct IrProgram {
+    pub blocks: Vec<Block>,
+}
+
+pub fn is_comparison(op: &str) -> bool {
+    matches!(op, "==" | "!=" | "<" | "<=" | ">" | ">=")
+}
+
+// Lowers a program to IR. Blocks for an `if` are laid out as then, else,
+// join; for a `while` as head, body, exit.
+pub fn lower(ast: &Stmt, ctx: &mut CompilationContext) -> IrProgram {
+    let mut lowering = Lowering { ctx, blocks: Vec::new(), label: "entry".to_string(), insts: Vec::new(), next_temp: 0 };
+    lowering.stmt(ast);
+    lowering.finish_block(Terminator::Halt, String::new());
+    IrProgram { blocks: lowering.blocks }
+}
+
+struct Lowering<'a> {
+    ctx: &'a mut CompilationContext,
+    blocks: Vec<Block>,
+    // The block being filled in.
+    label: String,
+    insts: Vec<Inst>,
+    next_temp: usize,
+}
+
+// True for numbers and variables, which are operands as they stand.
+fn is_leaf(e: &Expr) -> bool {
+    matches!(e, Expr::Number { .. } | Expr::Variable { .. })
+}
+
+// Rewrites `>` and `<=` into the forms the CPU tests directly, swapping the
+// operands.
+fn canonical_comparison<'e>(l: &'e Expr, op: &str, r: &'e Expr) -> (&'e Expr, String, &'e Expr) {
+    match op {
+        ">" => (r, "<".to_string(), l),
+        "<=" => (r, ">=".to_string(), l),
+        _ => (l, op.to_string(), r),
+    }
+}
+
+impl Lowering<'_> {
+    // Ends the current block with `terminator` and starts one named `next`.
+    fn finish_block(&mut self, terminator: Terminator, next: String) {
+        let label = std::mem::replace(&mut self.label, next);
+        let insts = std::mem::take(&mut self.insts);
+        self.blocks.push(Block { label, insts, terminator });
+    }
+
+    fn temp(&mut self) -> Operand {
+        self.next_temp += 1;
+        Operand::Temp(self.next_temp - 1)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Declare { name, val: Some(val), span } | Stmt::Assign { name, val, span } => {
+                self.expr_into(Some(Operand::Var(name.clone())), val, *span);
+            }
+            Stmt::Declare { name, val: None, span } => {
+                self.insts.push(Inst::Copy { dst: Operand::Var(name.clone()), src: Operand::Const(0), span: *span })
+            }
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                let id = self.ctx.labels.fresh();
+                let else_label = format!("else_{}", id);
+                let endif_label = format!("endif_{}", id);
+                self.cond(cond, format!("then_{}", id), else_label.clone());
+                self.stmt(then_branch);
+                self.finish_block(Terminator::Jump(endif_label.clone()), else_label);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+                self.finish_block(Terminator::Jump(endif_label.clone()), endif_label);
+            }
+            Stmt::While { cond, body, .. } => {
+                let id = self.ctx.labels.fresh();
+                let loop_label = format!("while_{}", id);
+                self.finish_block(Terminator::Jump(loop_label.clone()), loop_label.clone());
+                self.cond(cond, format!("body_{}", id), format!("endwhile_{}", id));
+                self.stmt(body);
+                self.finish_block(Terminator::Jump(loop_label), format!("endwhile_{}", id));
+            }
+            Stmt::Block { stmts, .. } => {
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+            }
+            // The value is computed into a temporary nobody reads.
+            Stmt::Expr(expr) => {
+                self.expr_into(None, expr, expr.span());
+            }
+        }
+    }
+
+    // Ends the current block with a branch on `cond`, continuing in the
+    // block `then_label`. Anything but a comparison is tested against zero.
+    fn cond(&mut self, cond: &Expr, then_label: String, else_label: String) {
+        let (l, op, r) = match cond {
+            Expr::Binary { l, op, r, .. } if is_comparison(op) => {
+                let (l, op, r) = canonical_comparison(l, op, r);
+                let (l, r) = self.operands(l, r);
+                (l, op, r)
+            }
+            _ => (self.operand(cond), "!=".to_string(), Operand::Const(0)),
+        };
+        let branch = Terminator::Branch { l, op, r, then_label: then_label.clone(), else_label, span: cond.span() };
+        self.finish_block(branch, then_label);
+    }
+
+    // An operand holding the value of `e`, computing it into a temporary
+    // unless it is a leaf.
+    fn operand(&mut self, e: &Expr) -> Operand {
+        match e {
+            Expr::Number { val, .. } => Operand::Const(*val),
+            Expr::Variable { name, .. } => Operand::Var(name.clone()),
+            _ => self.expr_into(None, e, e.span()),
+        }
+    }
+
+    // Operands for `first` and `second`. When `second` needs computing it is
+    // computed first, so that `first` is the most recent value and can be
+    // used straight from the accumulator.
+    fn operands(&mut self, first: &Expr, second: &Expr) -> (Operand, Operand) {
+        if is_leaf(second) {
+            let first = self.operand(first);
+            (first, self.operand(second))
+        } else {
+            let second = self.operand(second);
+            (self.operand(first), second)
+        }
+    }
+
+    // Computes `e` into `dst`, or into a new temporary if `dst` is `None`,
+    // and returns where the value went. Temporaries are numbered in the order
+    // they are written.
+    //
+    // A chain that leans left, like `a + b * c + d`, is as deep as it is long,
+    // so it is lowered with a work stack rather than by recursion: going down
+    // the chain, each right operand that must be computed before the rest of
+    // the chain is computed; coming back up, each link's instruction is
+    // emitted. Only the right operands and the bottom of the chain recurse.
+    fn expr_into(&mut self, mut dst: Option<Operand>, e: &Expr, span: Span) -> Operand {
+        // Each link, with its operator, whether the chain below it is its
+        // first operand, and its other operand if that was computed early.
+        let mut links = Vec::new();
+        let mut bottom = e;
+        while let Expr::Binary { l, op, r, .. } = bottom {
+            if !matches!(**l, Expr::Binary { .. }) {
+                break;
+            }
+            let (first, op, _) = canonical_comparison(l, op, r);
+            let chain_first = std::ptr::eq(first, &**l);
+            // As in `operands`: a computed second operand goes before the
+            // first. When the chain is second it goes first anyway.
+            let early = if chain_first && !is_leaf(r) { Some(self.operand(r)) } else { None };
+            links.push((bottom, op, chain_first, early));
+            bottom = l;
+        }
+        let mut value = match links.is_empty() {
+            true => self.node_into(dst.take(), bottom, span),
+            false => self.node_into(None, bottom, bottom.span()),
+        };
+        for (i, (link, op, chain_first, early)) in links.into_iter().enumerate().rev() {
+            let Expr::Binary { r, span: link_span, .. } = link else { unreachable!("links are binary") };
+            let other = match early {
+                Some(other) => other,
+                None => self.operand(r),
+            };
+            let (l, r) = if chain_first { (value, other) } else { (other, value) };
+            let (dst, span) = match i {
+                0 => (dst.take().unwrap_or_else(|| self.temp()), span),
+                _ => (self.temp(), *link_span),
+            };
+            self.insts.push(Inst::Binary { dst: dst.clone(), op, l, r, span });
+            value = dst;
+        }
+        value
+    }
+
+    // `expr_into` for one node, whose operands are computed recursively.
+    fn node_into(&mut self, dst: Option<Operand>, e: &Expr, span: Span) -> Operand {
+        let (op, l, r) = match e {
+            Expr::Number { .. } | Expr::Variable { .. } => {
+                let src = self.operand(e);
+                let dst = dst.unwrap_or_else(|| self.temp());
+                self.insts.push(Inst::Copy { dst: dst.clone(), src, span });
+                return dst;
+            }
+            Expr::Binary { l, op, r, .. } => {
+                let (l, op, r) = canonical_comparison(l, op, r);
+                let (l, r) = self.operands(l, r);
+                (op, l, r)
+            }
+            // `-e` is `0 - e`.
+            Expr::Unary { op, operand, span: unary_span } => {
+                let (l, r) = self.operands(&Expr::Number { val: 0, span: *unary_span }, operand);
+                (op.clone(), l, r)
+            }
+        };
+        let dst = dst.unwrap_or_else(|| self.temp());
+        self.insts.push(Inst::Binary { dst: dst.clone(), op, l, r, span });
+        dst
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Const(val) => write!(f, "{}", val),
+            Operand::Var(name) => write!(f, "{}", name),
+            Operand::Temp(n) => write!(f, "%t{}", n),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Copy { dst, src, .. } => write!(f, "{} = {}", dst, src),
+            Inst::Binary { dst, op, l, r, .. } => write!(f, "{} = {} {} {}", dst, l, op, r),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump(label) => write!(f, "goto {}", label),
+            Terminator::Branch { l, op, r, then_label, else_label, .. } => {
+                write!(f, "if {} {} {} goto {} else {}", l, op, r, then_label, else_label)
+            }
+            Terminator::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+// The textual dump behind `--emit ir`:
+//
+//   entry:
+//       x = 42
+//       if x == 0 goto then_0 else else_0
+//   then_0:
+//       ...
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.label)?;
+            for inst in &block.insts {
+                writeln!(f, "    {}", inst)?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::compile;
+    use crate::CompileOptions;
+
+    #[test]
+    fn test_lowers_input_program() {
+        let expected = "\
+entry:
+    x = 42
+    y = x + 5
+    if x == y goto then_0 else else_0
+then_0:
+    y = y + 1
+    goto endif_0
+else_0:
+    goto endif_0
+endif_0:
+    halt
+";
+        assert_eq!(compile(include_str!("../input.txt")).ir.to_string(), expected);
+    }
+
+    #[test]
+    fn test_nested_expressions_use_temporaries() {
+        // The right operand is computed first, so the left one is the latest
+        // value when the subtraction needs it.
+        let ir = compile("int a; int b; a = (a - b) - -(b * 3);").ir.to_string();
+        let expected = "\
+entry:
+    a = 0
+    b = 0
+    %t0 = b * 3
+    %t1 = 0 - %t0
+    %t2 = a - b
+    a = %t2 - %t1
+    halt
+";
+        assert_eq!(ir, expected);
+    }
+
+    // Lowers `a = t + t + ...` with 5000 terms `t`, built directly since the
+    // parser would not accept a chain this long.
+    fn lower_long_chain(term: impl Fn() -> Expr) -> String {
+        let mut chain = term();
+        for _ in 1..5000 {
+            chain = Expr::binary(chain, "+".to_string(), term());
+        }
+        let mut program = crate::parse("int a;", &CompileOptions::default()).unwrap();
+        let Stmt::Block { stmts, .. } = &mut program.ast else { unreachable!() };
+        stmts.push(Stmt::Assign { name: "a".to_string(), val: chain, span: Span::default() });
+        lower(&program.ast, &mut program.context).to_string()
+    }
+
+    fn variable() -> Expr {
+        Expr::Variable { name: "a".to_string(), span: Span::default() }
+    }
+
+    #[test]
+    fn test_long_chains_are_lowered_in_order() {
+        let ir = compile("int a; a = a + 1 - a * 2 + 3 < a;").ir.to_string();
+        assert!(ir.contains("    %t0 = a * 2\n    %t1 = a + 1\n    %t2 = %t1 - %t0\n    %t3 = %t2 + 3\n    a = %t3 < a\n"));
+        // As long as the chain is, lowering it does not recurse.
+        let ir = lower_long_chain(variable);
+        assert!(ir.contains("    %t4997 = %t4996 + a\n    a = %t4997 + a\n"));
+    }
+
+    #[test]
+    fn test_long_chains_of_computed_operands() {
+        // Each right operand is computed before the chain to its left, as it
+        // would be for a short chain.
+        let ir = compile("int a; a = a * 1 + a * 2 + a * 3;").ir.to_string();
+        assert!(ir.contains("    %t0 = a * 3\n    %t1 = a * 2\n    %t2 = a * 1\n    %t3 = %t2 + %t1\n    a = %t3 + %t0\n"));
+        let ir = lower_long_chain(|| Expr::binary(variable(), "*".to_string(), Expr::Number { val: 1, span: Span::default() }));
+        assert!(ir.contains("    %t4999 = a * 1\n    %t5000 = %t4999 + %t4998\n"));
+        assert!(ir.contains("    a = %t9997 + %t0\n"));
+    }
+
+    #[test]
+    fn test_comparisons_are_canonical() {
+        let ir = compile("int a; int b = a > 1; if (a <= b + 1) { a = 1; }").ir.to_string();
+        assert!(ir.contains("    b = 1 < a\n"));
+        assert!(ir.contains("    %t0 = b + 1\n    if %t0 >= a goto then_0 else else_0\n"));
+    }
+
+    #[test]
+    fn test_while_loop_blocks() {
+        let expected = "\
+entry:
+    i = 3
+    goto while_0
+while_0:
+    if i != 0 goto body_0 else endwhile_0
+body_0:
+    i = i - 1
+    goto while_0
+endwhile_0:
+    %t0 = 1 + i
+    halt
+";
+        // The expression statement computes into a temporary nobody reads.
+        assert_eq!(compile("int i = 3; while (i) { i = i - 1; } 1 + i;").ir.to_string(), expected);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use crate::context::CompilationContext;
+use crate::ir::{is_comparison, Block, Inst, IrProgram, Operand, Terminator};
+use crate::runtime;
+
+// Instruction selection: IR to assembly for the 8-bit CPU.
+//
+// Every instruction loads its left operand into A and its right operand into
+// B, operates, and stores A into the destination. Variables live at the
+// addresses the symbol table gave them; a temporary gets a scratch cell only
+// when its value has to wait in memory. When the very next instruction takes
+// a temporary as its left operand, it is left in A instead.
+//
+// Blocks are emitted in layout order. A jump to the next block is left out,
+// and so is the label of a block that is only ever fallen into.
+
+pub fn select(ir: &IrProgram, out: &mut dyn Write, ctx: &mut CompilationContext) -> io::Result<()> {
+    let mut selector = Selector { out, ctx, read: HashSet::new(), cells: HashMap::new(), in_a: None };
+    for block in &ir.blocks {
+        for inst in &block.insts {
+            selector.read.extend(inst_operands(inst).1.iter().filter_map(|op| temp(op)));
+        }
+        if let Terminator::Branch { l, r, .. } = &block.terminator {
+            selector.read.extend([l, r].into_iter().filter_map(temp));
+        }
+    }
+
+    let jumped_to = jump_targets(ir);
+    for (i, block) in ir.blocks.iter().enumerate() {
+        let next = ir.blocks.get(i + 1).map(|block| block.label.as_str());
+        if jumped_to.contains(block.label.as_str()) {
+            writeln!(selector.out, "{}:", block.label)?;
+        }
+        selector.block(block, next)?;
+    }
+    Ok(())
+}
+
+// Labels some emitted jump goes to.
+fn jump_targets(ir: &IrProgram) -> HashSet<&str> {
+    let mut targets = HashSet::new();
+    for (i, block) in ir.blocks.iter().enumerate() {
+        let next = ir.blocks.get(i + 1).map(|block| block.label.as_str());
+        match &block.terminator {
+            Terminator::Jump(target) if Some(target.as_str()) != next => {
+                targets.insert(target.as_str());
+            }
+            Terminator::Branch { then_label, else_label, .. } => {
+                targets.insert(else_label.as_str());
+                if Some(then_label.as_str()) != next {
+                    targets.insert(then_label.as_str());
+                }
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+fn temp(op: &Operand) -> Option<usize> {
+    match op {
+        Operand::Temp(n) => Some(*n),
+        _ => None,
+    }
+}
+
+// The destination and the operands an instruction reads, left one first.
+fn inst_operands(inst: &Inst) -> (&Operand, Vec<&Operand>) {
+    match inst {
+        Inst::Copy { dst, src, .. } => (dst, vec![src]),
+        Inst::Binary { dst, l, r, .. } => (dst, vec![l, r]),
+    }
+}
+
+// The operand an instruction or terminator loads into A first.
+fn left_operand(inst: Option<&Inst>, terminator: &Terminator) -> Option<Operand> {
+    match (inst, terminator) {
+        (Some(inst), _) => inst_operands(inst).1.first().map(|&op| op.clone()),
+        (None, Terminator::Branch { l, .. }) => Some(l.clone()),
+        (None, _) => None,
+    }
+}
+
+struct Selector<'a> {
+    out: &'a mut dyn Write,
+    ctx: &'a mut CompilationContext,
+    // Temporaries some instruction reads.
+    read: HashSet<usize>,
+    // Scratch cells of the temporaries waiting in memory.
+    cells: HashMap<usize, i32>,
+    // The temporary left in A for the next instruction.
+    in_a: Option<usize>,
+}
+
+impl Selector<'_> {
+    // Runs `select` with the IR behind it as the innermost node of the
+    // codegen trace, when one is being recorded.
+    fn traced(
+        &mut self,
+        label: impl FnOnce() -> String,
+        select: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let Some(trace) = self.ctx.trace.clone() else { return select(self) };
+        trace.enter(label());
+        let result = select(self);
+        trace.exit();
+        result
+    }
+
+    fn block(&mut self, block: &Block, next: Option<&str>) -> io::Result<()> {
+        for (i, inst) in block.insts.iter().enumerate() {
+            let then = left_operand(block.insts.get(i + 1), &block.terminator);
+            let span = inst.span();
+            self.traced(|| format!("{} at {}:{}", inst, span.line, span.column), |s| s.inst(inst, then))?;
+        }
+        let span = match &block.terminator {
+            Terminator::Branch { span, .. } => Some(*span),
+            _ => None,
+        };
+        let label = || match span {
+            Some(span) => format!("{} at {}:{}", block.terminator, span.line, span.column),
+            None => block.terminator.to_string(),
+        };
+        self.traced(label, |s| s.terminator(&block.terminator, next))
+    }
+
+    // `then` is what the following instruction loads into A first.
+    fn inst(&mut self, inst: &Inst, then: Option<Operand>) -> io::Result<()> {
+        match inst {
+            Inst::Copy { dst, src, .. } => {
+                self.load_a(src)?;
+                self.store(dst, then)
+            }
+            Inst::Binary { dst, op, l, r, .. } => {
+                self.load_a(l)?;
+                self.load_b(r)?;
+                match op.as_str() {
+                    "+" => writeln!(self.out, "add")?,
+                    "-" => writeln!(self.out, "sub")?,
+                    "*" => runtime::call_mul(self.out, self.ctx)?,
+                    "/" => runtime::call_div(self.out, self.ctx)?,
+                    "%" => runtime::call_mod(self.out, self.ctx)?,
+                    op if is_comparison(op) => self.materialize(op)?,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported binary op")),
+                }
+                self.store(dst, then)
+            }
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, next: Option<&str>) -> io::Result<()> {
+        match terminator {
+            Terminator::Jump(target) if Some(target.as_str()) == next => Ok(()),
+            Terminator::Jump(target) => writeln!(self.out, "jmp %{}", target),
+            Terminator::Branch { l, op, r, then_label, else_label, .. } => {
+                self.load_a(l)?;
+                self.load_b(r)?;
+                self.compare(op, else_label)?;
+                if Some(then_label.as_str()) != next {
+                    writeln!(self.out, "jmp %{}", then_label)?;
+                }
+                Ok(())
+            }
+            Terminator::Halt => writeln!(self.out, "hlt"),
+        }
+    }
+
+    fn load_a(&mut self, op: &Operand) -> io::Result<()> {
+        match op {
+            Operand::Temp(n) if self.in_a == Some(*n) => {
+                self.in_a = None;
+                Ok(())
+            }
+            Operand::Const(val) => writeln!(self.out, "ldi A {}", val),
+            Operand::Var(name) => writeln!(self.out, "lda {}", self.ctx.symbols.declare(name)?),
+            Operand::Temp(n) => {
+                let cell = self.take_cell(*n)?;
+                writeln!(self.out, "lda {}", cell)
+            }
+        }
+    }
+
+    fn load_b(&mut self, op: &Operand) -> io::Result<()> {
+        match op {
+            Operand::Const(val) => writeln!(self.out, "ldi B {}", val),
+            Operand::Var(name) => writeln!(self.out, "mov B M {}", self.ctx.symbols.declare(name)?),
+            Operand::Temp(n) => {
+                let cell = self.take_cell(*n)?;
+                writeln!(self.out, "mov B M {}", cell)
+            }
+        }
+    }
+
+    // The cell holding temporary `n`, which is free again once read.
+    fn take_cell(&mut self, n: usize) -> io::Result<i32> {
+        let cell = self.cells.remove(&n).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("temporary %t{} read before it is written", n))
+        })?;
+        self.ctx.symbols.release_temp(cell);
+        Ok(cell)
+    }
+
+    // Stores A into `dst`, unless it is a temporary that nothing reads or
+    // that `then` picks up straight from A.
+    fn store(&mut self, dst: &Operand, then: Option<Operand>) -> io::Result<()> {
+        match dst {
+            Operand::Var(name) => writeln!(self.out, "sta {}", self.ctx.symbols.declare(name)?),
+            Operand::Temp(n) if !self.read.contains(n) => Ok(()),
+            Operand::Temp(n) if then.as_ref() == Some(dst) => {
+                self.in_a = Some(*n);
+                Ok(())
+            }
+            Operand::Temp(n) => {
+                let cell = self.ctx.symbols.acquire_temp()?;
+                self.cells.insert(*n, cell);
+                writeln!(self.out, "sta {}", cell)
+            }
+            Operand::Const(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot assign to a constant")),
+        }
+    }
+
+    // Emits `cmp` and a jump to `false_label` taken when `A op B` does not
+    // hold. `cmp` sets Z when A == B and C when A < B (it borrows).
+    fn compare(&mut self, op: &str, false_label: &str) -> io::Result<()> {
+        let jump = match op {
+            "==" => "jnz",
+            "!=" => "jz",
+            "<" => "jnc",
+            ">=" => "jc",
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported comparison")),
+        };
+        writeln!(self.out, "cmp")?;
+        writeln!(self.out, "{} %{}", jump, false_label)
+    }
+
+    // Leaves 1 in A if `A op B` holds and 0 otherwise.
+    fn materialize(&mut self, op: &str) -> io::Result<()> {
+        let id = self.ctx.labels.fresh();
+        let false_label = format!("false_{}", id);
+        let end_label = format!("endcmp_{}", id);
+        self.compare(op, &false_label)?;
+        writeln!(self.out, "ldi A 1")?;
+        writeln!(self.out, "jmp %{}", end_label)?;
+        writeln!(self.out, "{}:", false_label)?;
+        writeln!(self.out, "ldi A 0")?;
+        writeln!(self.out, "{}:", end_label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::check_unique_labels;
+    use crate::test_support::compile;
+    use crate::CompileOptions;
+
+    #[test]
+    fn test_selects_input_program() {
+        let expected = ".text\nldi A 42\nsta 1\nlda 1\nldi B 5\nadd\nsta 2\nlda 1\nmov B M 2\ncmp\njnz %else_0\n\
+                        lda 2\nldi B 1\nadd\nsta 2\njmp %endif_0\nelse_0:\nendif_0:\nhlt\n";
+        assert_eq!(compile(include_str!("../input.txt")).asm, expected);
+    }
+
+    #[test]
+    fn test_nested_and_sequential_ifs_get_distinct_labels() {
+        let asm = compile(
+            "int x = 1; int y = 2;
+             if (x == y) { if (x == 1) { y = 3; } }
+             if (y == 2) { x = 4; } else { x = 5; }",
+        ).asm;
+        assert!(check_unique_labels(&asm).is_ok());
+        for id in 0..3 {
+            assert!(asm.contains(&format!("else_{}:", id)));
+            assert!(asm.contains(&format!("endif_{}:", id)));
+        }
+    }
+
+    #[test]
+    fn test_while_loop_jumps_back_to_its_head() {
+        let asm = compile("int i = 0; while (i == 0) { i = i + 1; while (i == 1) { i = i + 1; } }").asm;
+        assert!(check_unique_labels(&asm).is_ok());
+        let expected = "while_0:\nlda 1\nldi B 0\ncmp\njnz %endwhile_0\n";
+        assert!(asm.contains(expected));
+        assert!(asm.contains("jmp %while_1\nendwhile_1:\njmp %while_0\nendwhile_0:\n"));
+    }
+
+    #[test]
+    fn test_right_nested_expression_spills_to_temporary() {
+        // a + (b + c): b + c is computed first and parked in cell 4, the first
+        // free cell after a, b and c.
+        let asm = compile("int a = 1; int b = 2; int c = 3; a = a + (b + c);").asm;
+        assert!(asm.contains("lda 2\nmov B M 3\nadd\nsta 4\nlda 1\nmov B M 4\nadd\nsta 1\n"));
+    }
+
+    #[test]
+    fn test_right_leaning_chain_reuses_one_temporary() {
+        // Each level finishes with its temporary before the next one needs it.
+        let asm = compile("int a = 1; a = 1 - (2 - (3 - (4 - a)));").asm;
+        let expected = "ldi A 4\nmov B M 1\nsub\n\
+                        sta 2\nldi A 3\nmov B M 2\nsub\n\
+                        sta 2\nldi A 2\nmov B M 2\nsub\n\
+                        sta 2\nldi A 1\nmov B M 2\nsub\n\
+                        sta 1\n";
+        assert!(asm.contains(expected));
+    }
+
+    #[test]
+    fn test_nested_operands_on_both_sides() {
+        // (a - b) - (b - a): the right side is parked in cell 3 while the left
+        // side is computed in A, where it stays.
+        let asm = compile("int a = 5; int b = 2; a = (a - b) - (b - a);").asm;
+        let expected = "lda 2\nmov B M 1\nsub\nsta 3\nlda 1\nmov B M 2\nsub\nmov B M 3\nsub\nsta 1\n";
+        assert!(asm.contains(expected));
+    }
+
+    #[test]
+    fn test_variable_initializer_loads_the_variable() {
+        let asm = compile("int a = 1; int b = a;").asm;
+        assert!(asm.contains("lda 1\nsta 2\n"));
+    }
+
+    #[test]
+    fn test_relational_conditions_branch_directly() {
+        let cases = [
+            ("==", "lda 1\nmov B M 2\ncmp\njnz %else_0\n"),
+            ("!=", "lda 1\nmov B M 2\ncmp\njz %else_0\n"),
+            ("<", "lda 1\nmov B M 2\ncmp\njnc %else_0\n"),
+            (">=", "lda 1\nmov B M 2\ncmp\njc %else_0\n"),
+            (">", "lda 2\nmov B M 1\ncmp\njnc %else_0\n"),
+            ("<=", "lda 2\nmov B M 1\ncmp\njc %else_0\n"),
+        ];
+        for (op, expected) in cases {
+            let asm = compile(&format!("int a = 1; int b = 2; if (a {} b) {{ a = 3; }}", op)).asm;
+            assert!(asm.contains(expected), "{}:\n{}", op, asm);
+        }
+    }
+
+    #[test]
+    fn test_comparison_as_value_materializes_zero_or_one() {
+        // The `if`/`while` labels are numbered during lowering, before any
+        // comparison is selected; this program has none, so it gets 0.
+        let asm = compile("int a = 1; int b = a < 2;").asm;
+        let expected = "lda 1\nldi B 2\ncmp\njnc %false_0\nldi A 1\njmp %endcmp_0\nfalse_0:\nldi A 0\nendcmp_0:\nsta 2\n";
+        assert!(asm.contains(expected));
+    }
+
+    #[test]
+    fn test_plain_value_as_condition_tests_for_zero() {
+        let asm = compile("int a = 1; while (a) { a = a - 1; }").asm;
+        assert!(asm.contains("while_0:\nlda 1\nldi B 0\ncmp\njz %endwhile_0\n"));
+    }
+
+    #[test]
+    fn test_unread_temporaries_are_not_stored() {
+        let asm = compile("int a = 1; 2 + a; (a - 1) * 3;").asm;
+        assert!(asm.starts_with(".text\nldi A 1\nsta 1\nldi A 2\nmov B M 1\nadd\nlda 1\nldi B 1\nsub\nldi B 3\ncall %__mul\nhlt\n__mul:\n"));
+    }
+
+    #[test]
+    fn test_trace_names_the_ir_behind_each_line() {
+        let program = crate::parse("int a = 1;\na = a + 2;", &CompileOptions::default()).unwrap();
+        let mut ctx = program.context;
+        let ir = crate::ir::lower(&program.ast, &mut ctx);
+        let trace = crate::log::CodegenTrace::default();
+        ctx.trace = Some(trace.clone());
+        super::select(&ir, &mut trace.writer(&mut Vec::new()), &mut ctx).unwrap();
+        let expected = vec![
+            "ldi A 1          ; a = 1 at 1:1",
+            "sta 1            ; a = 1 at 1:1",
+            "lda 1            ; a = a + 2 at 2:1",
+            "ldi B 2          ; a = a + 2 at 2:1",
+            "add              ; a = a + 2 at 2:1",
+            "sta 1            ; a = a + 2 at 2:1",
+            "hlt              ; halt",
+        ];
+        assert_eq!(trace.take_lines(), expected);
+    }
+}
@@ -1,15 +1,28 @@
+use std::fmt;
+use crate::diagnostic::{Diagnostic, Span};
+
 
 #[derive(Clone,PartialEq,Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Tokentype {
     INT,
     IF,
     ELSE,
+    WHILE,
     IDENTIFIER,
     NUMBER,
     PLUS,
     MINUS,
+    STAR,
+    SLASH,
+    PERCENT,
     EQ,
     EQUAL,
+    NOTEQUAL,
+    LESS,
+    LESSEQUAL,
+    GREATER,
+    GREATEREQUAL,
     LPAREN,
     RPAREN,
     LBRACE,
@@ -21,74 +34,209 @@ pub enum Tokentype {
 pub struct Token {
     pub typ: Tokentype,
     pub value: String,
+    pub span: Span,
+}
+
+// `line:column TYPE value`, e.g. `1:5 IDENTIFIER x`, as in token dumps.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} {:?}", self.span.line, self.span.column, self.typ)?;
+        if !self.value.is_empty() {
+            write!(f, " {}", self.value)?;
+        }
+        Ok(())
+    }
+}
+
+// Source text that carries no tokens but that tools such as the formatter
+// want to keep. Only collected by a lexer made with `Lexer::with_trivia`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TriviaKind {
+    // A line holding nothing but whitespace.
+    BlankLine,
+    // `// ...` up to the end of the line.
+    LineComment,
+    // `/* ... */`, possibly spanning several lines.
+    BlockComment,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    // The comment as written, without trailing whitespace; empty for blank
+    // lines.
+    pub text: String,
+    pub span: Span,
 }
 
 pub struct Lexer {
     source: String,
     pos: usize,
+    line: usize,
+    line_start: usize,
+    keep_trivia: bool,
+    trivia: Vec<Trivia>,
 }
 
 impl Lexer {
     pub fn new(source: String) -> Self {
-        Lexer { source, pos: 0 }
+        Lexer { source, pos: 0, line: 1, line_start: 0, keep_trivia: false, trivia: Vec::new() }
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    // A lexer that also records trivia, to be collected with `take_trivia`
+    // after tokenizing.
+    pub fn with_trivia(source: String) -> Self {
+        Lexer { keep_trivia: true, ..Lexer::new(source) }
+    }
+
+    // Trivia found by `tokenize`, in source order.
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        std::mem::take(&mut self.trivia)
+    }
+
+    // True if the character after the current one is `ch`.
+    fn next_is(&self, ch: char) -> bool {
+        self.pos + 1 < self.source.len() && self.source.as_bytes()[self.pos + 1] as char == ch
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.pos, self.line, start - self.line_start + 1)
+    }
+
+    // Skips the comment starting at `start`. A `//` comment runs to the end of
+    // the line. A `/*` comment runs to the first `*/`; block comments do not
+    // nest, so `/* a /* b */` is one complete comment.
+    fn comment(&mut self, start: usize) -> Result<(), Diagnostic> {
+        let (line, column) = (self.line, start - self.line_start + 1);
+        let kind = if self.next_is('/') {
+            self.pos = self.source[start..].find('\n').map_or(self.source.len(), |i| start + i);
+            TriviaKind::LineComment
+        } else {
+            let Some(len) = self.source[start + 2..].find("*/") else {
+                self.pos = self.source.len();
+                return Err(Diagnostic::error(
+                    "Unterminated block comment".to_string(),
+                    Span::new(start, start + 2, line, column),
+                ));
+            };
+            self.pos = start + 2 + len + 2;
+            for (i, byte) in self.source[start..self.pos].bytes().enumerate() {
+                if byte == b'\n' {
+                    self.line += 1;
+                    self.line_start = start + i + 1;
+                }
+            }
+            TriviaKind::BlockComment
+        };
+        if self.keep_trivia {
+            let text = self.source[start..self.pos].trim_end().to_string();
+            self.trivia.push(Trivia { kind, text, span: Span::new(start, self.pos, line, column) });
+        }
+        Ok(())
+    }
+
+    // Splits the source into tokens. Unexpected characters are reported and
+    // skipped so that every bad character in the file shows up in one run.
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Vec<Diagnostic>> {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
         while self.pos < self.source.len() {
+            let start = self.pos;
             let cur = self.source.as_bytes()[self.pos] as char;
             if cur.is_whitespace() {
                 self.pos += 1;
+                if cur == '\n' {
+                    if self.keep_trivia && self.source[self.line_start..start].trim().is_empty() {
+                        let span = self.span_from(self.line_start);
+                        self.trivia.push(Trivia { kind: TriviaKind::BlankLine, text: String::new(), span });
+                    }
+                    self.line += 1;
+                    self.line_start = self.pos;
+                }
+                continue;
+            }
+            if cur == '/' && (self.next_is('/') || self.next_is('*')) {
+                if let Err(err) = self.comment(start) {
+                    errors.push(err);
+                }
                 continue;
             }
 
-            if cur.is_alphabetic() {
+            let (typ, value) = if cur.is_ascii_alphabetic() {
                 let mut identifier = String::new();
-                while self.pos < self.source.len() && self.source.as_bytes()[self.pos].is_ascii_alphanumeric() {                    
+                while self.pos < self.source.len() && self.source.as_bytes()[self.pos].is_ascii_alphanumeric() {
                     let ch = self.source.as_bytes()[self.pos] as char; // Get the current character
                     identifier.push(ch);
-                            self.pos += 1;
-                        if self.pos >= self.source.len() {
-                        break;
-                    }
+                    self.pos += 1;
                 }
                 match identifier.as_str() {
-                    "int" => tokens.push(Token { typ: Tokentype::INT, value: "int".to_string() }),
-                    "if" => tokens.push(Token { typ: Tokentype::IF, value: "if".to_string() }),
-                    "else" => tokens.push(Token { typ: Tokentype::ELSE, value: "else".to_string() }),
-                    _ => tokens.push(Token { typ: Tokentype::IDENTIFIER, value: identifier }),
+                    "int" => (Tokentype::INT, identifier),
+                    "if" => (Tokentype::IF, identifier),
+                    "else" => (Tokentype::ELSE, identifier),
+                    "while" => (Tokentype::WHILE, identifier),
+                    _ => (Tokentype::IDENTIFIER, identifier),
                 }
-            } else if cur.is_digit(10) {
+            } else if cur.is_ascii_digit() {
                 let mut number = String::new();
                 while self.pos < self.source.len() && self.source.as_bytes()[self.pos].is_ascii_digit() {
                     number.push(self.source.as_bytes()[self.pos] as char);
                     self.pos += 1;
                 }
-                tokens.push(Token { typ: Tokentype::NUMBER, value: number });
+                (Tokentype::NUMBER, number)
             } else {
-                match cur {
-                    '+' => tokens.push(Token { typ: Tokentype::PLUS, value: "+".to_string() }),
-                    '-' => tokens.push(Token { typ: Tokentype::MINUS, value: "-".to_string() }),
-                    '=' => {
-                                if self.pos + 1 < self.source.len() && self.source.as_bytes()[self.pos + 1] as char == '=' {
-                                    tokens.push(Token { typ: Tokentype::EQUAL, value: "==".to_string() });
-                                    self.pos += 1;
-                                } else {
-                                    tokens.push(Token { typ: Tokentype::EQ, value: "=".to_string() });
-                                }
-                            }
-                    '(' => tokens.push(Token { typ: Tokentype::LPAREN, value: "(".to_string() }),
-                    ')' => tokens.push(Token { typ: Tokentype::RPAREN, value: ")".to_string() }),
-                    '{' => tokens.push(Token { typ: Tokentype::LBRACE, value: "{".to_string() }),
-                    '}' => tokens.push(Token { typ: Tokentype::RBRACE, value: "}".to_string() }),
-                    ';' => tokens.push(Token { typ: Tokentype::SEMICOLON, value: ";".to_string() }),
-                    _ => panic!("Unexpected character: {}", cur),
-                }
+                let typ = match cur {
+                    '+' => Tokentype::PLUS,
+                    '-' => Tokentype::MINUS,
+                    '*' => Tokentype::STAR,
+                    '/' => Tokentype::SLASH,
+                    '%' => Tokentype::PERCENT,
+                    '=' if self.next_is('=') => {
+                        self.pos += 1;
+                        Tokentype::EQUAL
+                    }
+                    '=' => Tokentype::EQ,
+                    '!' if self.next_is('=') => {
+                        self.pos += 1;
+                        Tokentype::NOTEQUAL
+                    }
+                    '<' if self.next_is('=') => {
+                        self.pos += 1;
+                        Tokentype::LESSEQUAL
+                    }
+                    '<' => Tokentype::LESS,
+                    '>' if self.next_is('=') => {
+                        self.pos += 1;
+                        Tokentype::GREATEREQUAL
+                    }
+                    '>' => Tokentype::GREATER,
+                    '(' => Tokentype::LPAREN,
+                    ')' => Tokentype::RPAREN,
+                    '{' => Tokentype::LBRACE,
+                    '}' => Tokentype::RBRACE,
+                    ';' => Tokentype::SEMICOLON,
+                    _ => {
+                        // Skip the whole character, which may be more than one byte.
+                        let ch = self.source[self.pos..].chars().next().unwrap();
+                        self.pos += ch.len_utf8();
+                        errors.push(Diagnostic::error(
+                            format!("Unexpected character: {}", ch),
+                            self.span_from(start),
+                        ));
+                        continue;
+                    }
+                };
                 self.pos += 1;
-            }
+                (typ, self.source[start..self.pos].to_string())
+            };
+            tokens.push(Token { typ, value, span: self.span_from(start) });
+        }
+        let eof = self.span_from(self.pos);
+        tokens.push(Token { typ: Tokentype::END, value: "".to_string(), span: eof });
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
         }
-        tokens.push(Token { typ: Tokentype::END, value: "".to_string() });
-        tokens
     }
 }
 
@@ -96,19 +244,27 @@ impl Lexer {
 mod tests {
     use super::*;
 
+    fn tok(typ: Tokentype, value: &str) -> Token {
+        Token { typ, value: value.to_string(), span: Span::default() }
+    }
+
+    fn strip_spans(tokens: Vec<Token>) -> Vec<Token> {
+        tokens.into_iter().map(|t| Token { span: Span::default(), ..t }).collect()
+    }
+
     #[test]
     fn test_tokenize_identifiers() {
         let source = String::from("int a = 5;");
         let mut lexer = Lexer::new(source);
-        let tokens = lexer.tokenize();
+        let tokens = strip_spans(lexer.tokenize().unwrap());
 
         let expected_tokens = vec![
-            Token { typ: Tokentype::INT, value: "int".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "a".to_string() },
-            Token { typ: Tokentype::EQ, value: "=".to_string() },
-            Token { typ: Tokentype::NUMBER, value: "5".to_string() },
-            Token { typ: Tokentype::SEMICOLON, value: ";".to_string() },
-            Token { typ: Tokentype::END, value: "".to_string() },
+            tok(Tokentype::INT, "int"),
+            tok(Tokentype::IDENTIFIER, "a"),
+            tok(Tokentype::EQ, "="),
+            tok(Tokentype::NUMBER, "5"),
+            tok(Tokentype::SEMICOLON, ";"),
+            tok(Tokentype::END, ""),
         ];
 
         assert_eq!(tokens, expected_tokens);
@@ -118,33 +274,33 @@ mod tests {
     fn test_tokenize_if_else() {
         let source = String::from("if (x == y) { y = y + 1; } else { y = y - 1; }");
         let mut lexer = Lexer::new(source);
-        let tokens = lexer.tokenize();
+        let tokens = strip_spans(lexer.tokenize().unwrap());
 
         let expected_tokens = vec![
-            Token { typ: Tokentype::IF, value: "if".to_string() },
-            Token { typ: Tokentype::LPAREN, value: "(".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "x".to_string() },
-            Token { typ: Tokentype::EQUAL, value: "==".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::RPAREN, value: ")".to_string() },
-            Token { typ: Tokentype::LBRACE, value: "{".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::EQ, value: "=".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::PLUS, value: "+".to_string() },
-            Token { typ: Tokentype::NUMBER, value: "1".to_string() },
-            Token { typ: Tokentype::SEMICOLON, value: ";".to_string() },
-            Token { typ: Tokentype::RBRACE, value: "}".to_string() },
-            Token { typ: Tokentype::ELSE, value: "else".to_string() },
-            Token { typ: Tokentype::LBRACE, value: "{".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::EQ, value: "=".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::MINUS, value: "-".to_string() },
-            Token { typ: Tokentype::NUMBER, value: "1".to_string() },
-            Token { typ: Tokentype::SEMICOLON, value: ";".to_string() },
-            Token { typ: Tokentype::RBRACE, value: "}".to_string() },
-            Token { typ: Tokentype::END, value: "".to_string() },
+            tok(Tokentype::IF, "if"),
+            tok(Tokentype::LPAREN, "("),
+            tok(Tokentype::IDENTIFIER, "x"),
+            tok(Tokentype::EQUAL, "=="),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::RPAREN, ")"),
+            tok(Tokentype::LBRACE, "{"),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::EQ, "="),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::PLUS, "+"),
+            tok(Tokentype::NUMBER, "1"),
+            tok(Tokentype::SEMICOLON, ";"),
+            tok(Tokentype::RBRACE, "}"),
+            tok(Tokentype::ELSE, "else"),
+            tok(Tokentype::LBRACE, "{"),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::EQ, "="),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::MINUS, "-"),
+            tok(Tokentype::NUMBER, "1"),
+            tok(Tokentype::SEMICOLON, ";"),
+            tok(Tokentype::RBRACE, "}"),
+            tok(Tokentype::END, ""),
         ];
 
         assert_eq!(tokens, expected_tokens);
@@ -154,19 +310,151 @@ mod tests {
     fn test_tokenize_complex_expression() {
         let source = String::from("int x = 42 + y;");
         let mut lexer = Lexer::new(source);
-        let tokens = lexer.tokenize();
+        let tokens = strip_spans(lexer.tokenize().unwrap());
 
         let expected_tokens = vec![
-            Token { typ: Tokentype::INT, value: "int".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "x".to_string() },
-            Token { typ: Tokentype::EQ, value: "=".to_string() },
-            Token { typ: Tokentype::NUMBER, value: "42".to_string() },
-            Token { typ: Tokentype::PLUS, value: "+".to_string() },
-            Token { typ: Tokentype::IDENTIFIER, value: "y".to_string() },
-            Token { typ: Tokentype::SEMICOLON, value: ";".to_string() },
-            Token { typ: Tokentype::END, value: "".to_string() },
+            tok(Tokentype::INT, "int"),
+            tok(Tokentype::IDENTIFIER, "x"),
+            tok(Tokentype::EQ, "="),
+            tok(Tokentype::NUMBER, "42"),
+            tok(Tokentype::PLUS, "+"),
+            tok(Tokentype::IDENTIFIER, "y"),
+            tok(Tokentype::SEMICOLON, ";"),
+            tok(Tokentype::END, ""),
         ];
 
         assert_eq!(tokens, expected_tokens);
     }
+
+    #[test]
+    fn test_tokenize_while() {
+        let mut lexer = Lexer::new(String::from("while (x == 1) { }"));
+        let tokens = strip_spans(lexer.tokenize().unwrap());
+        assert_eq!(tokens[0], tok(Tokentype::WHILE, "while"));
+        assert_eq!(tokens[1], tok(Tokentype::LPAREN, "("));
+    }
+
+    #[test]
+    fn test_tokenize_relational_operators() {
+        let mut lexer = Lexer::new(String::from("== != < <= > >= ="));
+        let tokens = strip_spans(lexer.tokenize().unwrap());
+        let expected_tokens = vec![
+            tok(Tokentype::EQUAL, "=="),
+            tok(Tokentype::NOTEQUAL, "!="),
+            tok(Tokentype::LESS, "<"),
+            tok(Tokentype::LESSEQUAL, "<="),
+            tok(Tokentype::GREATER, ">"),
+            tok(Tokentype::GREATEREQUAL, ">="),
+            tok(Tokentype::EQ, "="),
+            tok(Tokentype::END, ""),
+        ];
+        assert_eq!(tokens, expected_tokens);
+    }
+
+    #[test]
+    fn test_tokenize_multiplicative_operators() {
+        let mut lexer = Lexer::new(String::from("a*b/c%d"));
+        let tokens = strip_spans(lexer.tokenize().unwrap());
+        let types: Vec<Tokentype> = tokens.into_iter().map(|t| t.typ).collect();
+        assert_eq!(
+            types,
+            vec![
+                Tokentype::IDENTIFIER,
+                Tokentype::STAR,
+                Tokentype::IDENTIFIER,
+                Tokentype::SLASH,
+                Tokentype::IDENTIFIER,
+                Tokentype::PERCENT,
+                Tokentype::IDENTIFIER,
+                Tokentype::END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let mut lexer = Lexer::new(String::from("x == 10;\n  y;"));
+        let spans: Vec<Span> = lexer.tokenize().unwrap().iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 1, 1, 1),
+                Span::new(2, 4, 1, 3),
+                Span::new(5, 7, 1, 6),
+                Span::new(7, 8, 1, 8),
+                Span::new(11, 12, 2, 3),
+                Span::new(12, 13, 2, 4),
+                Span::new(13, 13, 2, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unexpected_characters_are_all_reported() {
+        let mut lexer = Lexer::new(String::from("int x = 5 @ 3;\nx = é;"));
+        let errors = lexer.tokenize().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "Unexpected character: @");
+        assert_eq!(errors[0].span, Span::new(10, 11, 1, 11));
+        assert_eq!(errors[1].message, "Unexpected character: é");
+        assert_eq!(errors[1].span, Span::new(19, 21, 2, 5));
+    }
+
+    #[test]
+    fn test_comments_are_skipped() {
+        let mut lexer = Lexer::new(String::from("a // one / two\n/* three\n * four */ b /**/ / c"));
+        let tokens = strip_spans(lexer.tokenize().unwrap());
+        assert_eq!(
+            tokens,
+            vec![
+                tok(Tokentype::IDENTIFIER, "a"),
+                tok(Tokentype::IDENTIFIER, "b"),
+                tok(Tokentype::SLASH, "/"),
+                tok(Tokentype::IDENTIFIER, "c"),
+                tok(Tokentype::END, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_do_not_nest() {
+        let mut lexer = Lexer::new(String::from("/* a /* b */ c */"));
+        let tokens = strip_spans(lexer.tokenize().unwrap());
+        assert_eq!(tokens[0], tok(Tokentype::IDENTIFIER, "c"));
+        assert_eq!(tokens[1], tok(Tokentype::STAR, "*"));
+    }
+
+    #[test]
+    fn test_lines_are_counted_through_block_comments() {
+        let mut lexer = Lexer::new(String::from("/*\n\n*/ x"));
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0].span, Span::new(7, 8, 3, 4));
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut lexer = Lexer::new(String::from("int x;\n  /* never closed\nx = 1;"));
+        let errors = lexer.tokenize().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment");
+        assert_eq!(errors[0].span, Span::new(9, 11, 2, 3));
+    }
+
+    #[test]
+    fn test_trivia_is_kept_on_request() {
+        let source = "x; // end\n\n/* a\n b */ y;";
+        assert!(Lexer::new(source.to_string()).take_trivia().is_empty());
+        let mut lexer = Lexer::with_trivia(source.to_string());
+        lexer.tokenize().unwrap();
+        let trivia: Vec<(TriviaKind, String, usize)> =
+            lexer.take_trivia().into_iter().map(|t| (t.kind, t.text, t.span.line)).collect();
+        assert_eq!(
+            trivia,
+            vec![
+                (TriviaKind::LineComment, "// end".to_string(), 1),
+                (TriviaKind::BlankLine, String::new(), 2),
+                (TriviaKind::BlockComment, "/* a\n b */".to_string(), 3),
+            ]
+        );
+    }
 }
@@ -0,0 +1,239 @@
+use std::io::{self, Write};
+
+// The SimpleLang compiler as a library. `compile` runs the whole pipeline;
+// `tokenize` and `parse` stop after the earlier stages. Every stage's module
+// is public too, for tools that need to go further than these entry points.
+pub mod lexer;
+pub mod parser;
+pub mod ast;
+pub mod fold;
+pub mod ir;
+pub mod isel;
+pub mod peephole;
+pub mod context;
+pub mod diagnostic;
+pub mod runtime;
+pub mod sim;
+pub mod assembler;
+pub mod interp;
+pub mod printer;
+pub mod formatter;
+pub mod log;
+#[cfg(test)]
+mod difftest;
+#[cfg(test)]
+mod test_support;
+pub mod fuzz;
+
+use assembler::Program;
+use ast::Stmt;
+use ir::IrProgram;
+use context::{check_unique_labels, CompilationContext, SymbolTable};
+use diagnostic::Diagnostic;
+use lexer::{Lexer, Token};
+use log::{Channel, CodegenTrace, Logger};
+use parser::Parser;
+
+// The highest useful `CompileOptions::opt_level`.
+pub const MAX_OPT_LEVEL: u8 = 2;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CompileOptions {
+    // Also assemble the listing into `CompiledProgram::rom`.
+    pub assemble: bool,
+    // 0, the default here and on the command line, compiles the program as
+    // written; 1 and up fold constants first, and 2 also runs the peephole
+    // pass over the listing.
+    pub opt_level: u8,
+    // Where the token dump and the parser and codegen traces go. The default
+    // logger is silent.
+    pub logger: Logger,
+}
+
+// A program that lexed and parsed without errors.
+pub struct ParsedProgram {
+    pub tokens: Vec<Token>,
+    pub ast: Stmt,
+    pub warnings: Vec<Diagnostic>,
+    // The variables the parser declared, ready for code generation.
+    pub context: CompilationContext,
+}
+
+pub struct CompiledProgram {
+    pub tokens: Vec<Token>,
+    // The tree that was lowered, after any optimization.
+    pub ast: Stmt,
+    pub ir: IrProgram,
+    // The assembly listing, runtime library included.
+    pub asm: String,
+    // The ROM image, when `CompileOptions::assemble` is set.
+    pub rom: Option<Vec<u8>>,
+    // How many instructions the peephole pass removed from the listing.
+    pub instructions_saved: usize,
+    // Where each variable lives in data memory.
+    pub symbols: SymbolTable,
+    pub warnings: Vec<Diagnostic>,
+}
+
+pub fn tokenize(source: &str, options: &CompileOptions) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let tokens = Lexer::new(source.to_string()).tokenize()?;
+    if options.logger.enabled(Channel::Tokens) {
+        for token in &tokens {
+            options.logger.log(Channel::Tokens, token);
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn parse(source: &str, options: &CompileOptions) -> Result<ParsedProgram, Vec<Diagnostic>> {
+    let tokens = tokenize(source, options)?;
+    let mut parser = Parser::new(tokens.clone());
+    if options.logger.enabled(Channel::Parser) {
+        parser.enable_trace();
+    }
+    let result = parser.parse();
+    for line in parser.take_trace() {
+        options.logger.log(Channel::Parser, line);
+    }
+    let ast = result?;
+    let warnings = parser.take_warnings();
+    Ok(ParsedProgram { tokens, ast, warnings, context: parser.into_context() })
+}
+
+pub fn compile(source: &str, options: &CompileOptions) -> Result<CompiledProgram, Vec<Diagnostic>> {
+    let ParsedProgram { tokens, mut ast, warnings, mut context } = parse(source, options)?;
+    if options.opt_level >= 1 {
+        fold::fold_constants(&mut ast);
+    }
+    // Past parsing, errors concern the program as a whole.
+    let error = |message: String| vec![Diagnostic::error(message, ast.span())];
+
+    if options.logger.enabled(Channel::Codegen) {
+        context.trace = Some(CodegenTrace::default());
+    }
+    let ir = ir::lower(&ast, &mut context);
+    let mut asm = select_asm(&ir, &mut context).map_err(|err| error(err.to_string()))?;
+    if let Some(trace) = context.trace.take() {
+        for line in trace.take_lines() {
+            options.logger.log(Channel::Codegen, line);
+        }
+    }
+    let mut instructions_saved = 0;
+    if options.opt_level >= 2 {
+        (asm, instructions_saved) = peephole::optimize(&asm);
+        options.logger.log(Channel::Info, format!("peephole pass saved {} instructions", instructions_saved));
+    }
+
+    let rom = if options.assemble {
+        let program = Program::parse(&asm).map_err(|err| error(err.to_string()))?;
+        let image = assembler::assemble(&program).map_err(|err| error(err.to_string()))?;
+        options.logger.log(Channel::Info, format!("ROM image is {} bytes", image.len()));
+        Some(image)
+    } else {
+        None
+    };
+    Ok(CompiledProgram { tokens, ast, ir, asm, rom, instructions_saved, symbols: context.symbols, warnings })
+}
+
+// Generates the assembly listing for a parsed program.
+pub fn generate_asm(ast: &Stmt, ctx: &mut CompilationContext) -> io::Result<String> {
+    let ir = ir::lower(ast, ctx);
+    select_asm(&ir, ctx)
+}
+
+// The assembly listing for a program in IR. When `ctx.trace` is set, every
+// line of it is recorded there too.
+pub fn select_asm(ir: &IrProgram, ctx: &mut CompilationContext) -> io::Result<String> {
+    let mut asm = Vec::new();
+    let trace = ctx.trace.clone();
+    let mut traced;
+    let out: &mut dyn Write = match &trace {
+        Some(trace) => {
+            traced = trace.writer(&mut asm);
+            &mut traced
+        }
+        None => &mut asm,
+    };
+    writeln!(out, ".text")?;
+    isel::select(ir, out, ctx)?;
+    if let Some(trace) = &trace {
+        trace.enter("runtime library".to_string());
+    }
+    runtime::generate_runtime(out, ctx)?;
+    if let Some(trace) = &trace {
+        trace.exit();
+    }
+    let asm = String::from_utf8_lossy(&asm).into_owned();
+    check_unique_labels(&asm)?;
+    Ok(asm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_exposes_every_stage() {
+        let options = CompileOptions { assemble: true, ..CompileOptions::default() };
+        let program = compile("int x = 300;\nx = x + 1;", &options).unwrap();
+        assert_eq!(program.tokens[0].to_string(), "1:1 INT int");
+        assert_eq!(printer::to_sexpr(&program.ast).lines().next(), Some("(block"));
+        assert!(program.ir.to_string().starts_with("entry:\n    x = 44\n"));
+        assert!(program.asm.starts_with(".text\nldi A 44\nsta 1\n"));
+        assert_eq!(program.rom.as_deref().map(|rom| &rom[..4]), Some(&[0x10, 44, 0x21, 1][..]));
+        assert_eq!(program.symbols.variables(), vec![("x", 1)]);
+        assert_eq!(program.warnings[0].message, "Number 300 does not fit in 8 bits and will wrap around.");
+    }
+
+    #[test]
+    fn test_opt_level_folds_constants() {
+        let source = "int y = 5 + 3;\nif (1 == 1) { y = y + 0; }";
+        let unoptimized = compile(source, &CompileOptions::default()).unwrap();
+        assert!(unoptimized.asm.starts_with(".text\nldi A 5\nldi B 3\nadd\n"));
+        let options = CompileOptions { opt_level: 1, ..CompileOptions::default() };
+        let program = compile(source, &options).unwrap();
+        assert_eq!(program.ir.to_string(), "entry:\n    y = 8\n    y = y\n    halt\n");
+        assert!(!program.asm.contains("cmp"));
+    }
+
+    #[test]
+    fn test_opt_level_2_runs_peephole_pass() {
+        let options = CompileOptions { opt_level: 2, ..CompileOptions::default() };
+        let program = compile(include_str!("../input.txt"), &options).unwrap();
+        assert_eq!(program.instructions_saved, 2);
+        assert!(program.asm.starts_with(".text\nldi A 42\nsta 1\nldi B 5\n"));
+        assert!(!program.asm.contains("jmp"));
+        assert_eq!(compile(include_str!("../input.txt"), &CompileOptions::default()).unwrap().instructions_saved, 0);
+    }
+
+    #[test]
+    fn test_errors_come_back_as_diagnostics() {
+        let errors = compile("int x = ;", &CompileOptions::default()).err().unwrap();
+        assert_eq!(errors[0].message, "Expected expression. Found ';'");
+        let errors = compile("int x = 1 $ 2;", &CompileOptions::default()).err().unwrap();
+        assert_eq!(errors[0].message, "Unexpected character: $");
+    }
+
+    #[test]
+    fn test_programs_too_large_for_rom() {
+        let source: String = (0..70).map(|i| format!("int x{} = 1;\n", i)).collect();
+        assert!(compile(&source, &CompileOptions::default()).unwrap().rom.is_none());
+        let options = CompileOptions { assemble: true, ..CompileOptions::default() };
+        let errors = compile(&source, &options).err().unwrap();
+        assert_eq!(errors[0].message, "program needs 281 bytes but the ROM holds 256");
+        assert_eq!(errors[0].span.line, 1);
+    }
+
+    #[test]
+    fn test_data_memory_holds_255_cells() {
+        let source: String = (1..=255).map(|i| format!("int v{};\n", i)).collect();
+        assert!(compile(&source, &CompileOptions::default()).is_ok());
+        // A 256th variable does not fit.
+        let errors = compile(&format!("{}int x;\n", source), &CompileOptions::default()).err().unwrap();
+        assert_eq!(errors[0].message, "Semantic Error: no data memory left for 'x': all 255 cells are in use.");
+        assert_eq!((errors[0].span.line, errors[0].span.column), (256, 5));
+        // Nor does a temporary.
+        let errors = compile(&format!("{}v1 = (v1 + 1) - (v2 + 1);\n", source), &CompileOptions::default()).err().unwrap();
+        assert_eq!(errors[0].message, "no data memory left for a temporary: all 255 cells are in use");
+    }
+}
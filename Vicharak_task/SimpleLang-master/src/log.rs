@@ -0,0 +1,157 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Developer output. Everything is off by default and goes to stderr, so it
+// never mixes with a program's results on stdout. Each `-v` turns on one more
+// channel:
+//
+//   -v     info     what the compiler did, e.g. which file it wrote
+//          tokens   every token the lexer produced
+//   -vv    parser   the grammar rules the parser entered, and recoveries
+//   -vvv   codegen  every emitted line next to the IR it was selected from
+//
+// `--trace-codegen` turns on the codegen channel alone.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    Info,
+    Tokens,
+    Parser,
+    Codegen,
+}
+
+impl Channel {
+    fn name(self) -> &'static str {
+        match self {
+            Channel::Info => "info",
+            Channel::Tokens => "tokens",
+            Channel::Parser => "parser",
+            Channel::Codegen => "codegen",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Logger {
+    verbosity: usize,
+    trace_codegen: bool,
+}
+
+impl Logger {
+    pub fn new(verbosity: usize, trace_codegen: bool) -> Self {
+        Logger { verbosity, trace_codegen }
+    }
+
+    pub fn enabled(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::Info | Channel::Tokens => self.verbosity >= 1,
+            Channel::Parser => self.verbosity >= 2,
+            Channel::Codegen => self.verbosity >= 3 || self.trace_codegen,
+        }
+    }
+
+    pub fn log(&self, channel: Channel, message: impl fmt::Display) {
+        if self.enabled(channel) {
+            eprintln!("[{}] {}", channel.name(), message);
+        }
+    }
+}
+
+// Pairs each line of generated assembly with the IR instruction being
+// selected when it was written. Instruction selection pushes and pops them
+// through the copy in `CompilationContext`, while a `TraceWriter` sharing the
+// same state watches the output.
+#[derive(Clone, Default)]
+pub struct CodegenTrace {
+    state: Rc<RefCell<TraceState>>,
+}
+
+#[derive(Default)]
+struct TraceState {
+    nodes: Vec<String>,
+    partial: Vec<u8>,
+    lines: Vec<String>,
+}
+
+impl CodegenTrace {
+    pub fn enter(&self, node: String) {
+        self.state.borrow_mut().nodes.push(node);
+    }
+
+    pub fn exit(&self) {
+        self.state.borrow_mut().nodes.pop();
+    }
+
+    // Wraps `inner` so that every line written through it is recorded.
+    pub fn writer<'a>(&self, inner: &'a mut dyn Write) -> TraceWriter<'a> {
+        TraceWriter { inner, trace: self.clone() }
+    }
+
+    // The recorded lines, as `<assembly> ; <node>`.
+    pub fn take_lines(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.borrow_mut().lines)
+    }
+}
+
+pub struct TraceWriter<'a> {
+    inner: &'a mut dyn Write,
+    trace: CodegenTrace,
+}
+
+impl Write for TraceWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_all(buf)?;
+        let mut state = self.trace.state.borrow_mut();
+        state.partial.extend_from_slice(buf);
+        while let Some(end) = state.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = state.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).into_owned();
+            let node = state.nodes.last().map_or("-", String::as_str);
+            let entry = format!("{:<16} ; {}", line, node);
+            state.lines.push(entry);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channels_by_verbosity() {
+        let quiet = Logger::default();
+        assert!(!quiet.enabled(Channel::Info) && !quiet.enabled(Channel::Codegen));
+        let verbose = Logger::new(2, false);
+        assert!(verbose.enabled(Channel::Tokens) && verbose.enabled(Channel::Parser));
+        assert!(!verbose.enabled(Channel::Codegen));
+        let codegen_only = Logger::new(0, true);
+        assert!(codegen_only.enabled(Channel::Codegen) && !codegen_only.enabled(Channel::Tokens));
+    }
+
+    #[test]
+    fn test_trace_pairs_lines_with_innermost_node() {
+        let trace = CodegenTrace::default();
+        let mut out = Vec::new();
+        let mut writer = trace.writer(&mut out);
+        trace.enter("outer".to_string());
+        write!(writer, "ldi A ").unwrap();
+        trace.enter("inner".to_string());
+        writeln!(writer, "1\nsta 2").unwrap();
+        trace.exit();
+        writeln!(writer, "add").unwrap();
+        trace.exit();
+        drop(writer);
+        assert_eq!(out, b"ldi A 1\nsta 2\nadd\n");
+        assert_eq!(
+            trace.take_lines(),
+            vec!["ldi A 1          ; inner", "sta 2            ; inner", "add              ; outer"]
+        );
+    }
+}
@@ -1,54 +1,219 @@
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, Read, Write};
 use std::path::Path;
-mod lexer;
-mod parser;
-mod ast;
+use std::panic;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use compiler::assembler::{self, Program};
+use compiler::diagnostic::Diagnostic;
+use compiler::log::{Channel, Logger};
+use compiler::sim::Cpu;
+use compiler::{formatter, fuzz, interp, printer, CompileOptions, CompiledProgram, ParsedProgram};
+mod cli;
 
-use lexer::Lexer;
-use parser::Parser;
+use cli::{Command, Emit, Options};
+
+// The step limit `run` passes to `Cpu::run`: ten times the interpreter's, since
+// one statement takes several instructions.
+const MAX_SIM_STEPS: usize = 10_000_000;
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input_file>", args[0]);
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid arguments"));
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return writeln!(io::stdout(), "{}", cli::USAGE);
     }
+    let options = cli::parse_args(&args).unwrap_or_else(|message| {
+        eprintln!("error: {}\n\n{}", message, cli::USAGE);
+        process::exit(2);
+    });
+    let logger = Logger::new(options.verbosity, options.trace_codegen);
     //set env variable rust_backtrace=1 to see backtrace
     env::set_var("RUST_BACKTRACE", "1");
-    
-    let input_filename = &args[1];
-    let source_code = read_file(input_filename)?;
-    let mut lexer = Lexer::new(source_code);
-    let tokens = lexer.tokenize();
-    //print tokens with token number 
-    for (i, token) in tokens.iter().enumerate() {
-        println!("Token {}: {:?}", i, token);
-    }
-    let mut parser = Parser::new(tokens);
-
-    let ast = parser.parse();
-
-    let output_filename = generate_output_filename(input_filename);
-
-    let mut output_file = File::create(output_filename.clone())?;
-    writeln!(output_file, ".text")?;
-    ast.generate_code(&mut output_file)?;
-    writeln!(output_file, "hlt")?; 
-    println!("Assembly code successfully written to {}", output_filename);
+
+    if options.command == Command::Fuzz {
+        return run_fuzzer(options.input.as_deref().unwrap_or_default());
+    }
+    let input_filename = options.input.as_deref().unwrap_or("<stdin>");
+    let source_code = read_input(options.input.as_deref())?;
+    let compile_options = CompileOptions {
+        assemble: matches!(options.emit, Emit::Bin | Emit::Hex),
+        opt_level: options.opt_level,
+        logger,
+    };
+    match options.command {
+        Command::Fmt => run_formatter(input_filename, &source_code, &options, &logger),
+        Command::Build => build(input_filename, &source_code, &options, &compile_options),
+        Command::Check => {
+            parse(input_filename, &source_code, &compile_options);
+            logger.log(Channel::Info, format!("{} has no errors", input_filename));
+            Ok(())
+        }
+        Command::Eval => {
+            let program = parse(input_filename, &source_code, &compile_options);
+            let env = interp::interpret(&program.ast, interp::DEFAULT_STEP_LIMIT).unwrap_or_else(|err| fail(err));
+            for (name, val) in env.variables() {
+                println!("{} = {}", name, val);
+            }
+            Ok(())
+        }
+        Command::Run => run_program(&compile(input_filename, &source_code, &compile_options)),
+        Command::Fuzz => unreachable!("handled above"),
+    }
+}
+
+// Compiles the program as far as `--emit` asks and writes the result.
+fn build(input_filename: &str, source_code: &str, options: &Options, compile_options: &CompileOptions) -> io::Result<()> {
+    let output = match options.emit {
+        Emit::Tokens => {
+            let tokens = compiler::tokenize(source_code, compile_options)
+                .unwrap_or_else(|errors| report_and_exit(input_filename, source_code, &errors));
+            tokens.iter().map(|token| format!("{}\n", token)).collect::<String>().into_bytes()
+        }
+        Emit::Ast(format) => {
+            let program = parse(input_filename, source_code, compile_options);
+            printer::dump_ast(&program.ast, format).into_bytes()
+        }
+        Emit::Ir => compile(input_filename, source_code, compile_options).ir.to_string().into_bytes(),
+        Emit::Asm => compile(input_filename, source_code, compile_options).asm.into_bytes(),
+        Emit::Bin => compile(input_filename, source_code, compile_options).rom.unwrap_or_default(),
+        Emit::Hex => {
+            let program = compile(input_filename, source_code, compile_options);
+            assembler::intel_hex(&program.rom.unwrap_or_default()).into_bytes()
+        }
+    };
+
+    let default_path = match (&options.input, options.emit.extension()) {
+        (Some(input), Some(extension)) => Some(generate_output_filename(input, extension)),
+        _ => None,
+    };
+    write_output(options.output.as_deref().or(default_path.as_deref()), &output, &compile_options.logger)
+}
+
+// Parses the program, exiting with rendered diagnostics if it has errors.
+fn parse(input_filename: &str, source_code: &str, options: &CompileOptions) -> ParsedProgram {
+    let program = compiler::parse(source_code, options)
+        .unwrap_or_else(|errors| report_and_exit(input_filename, source_code, &errors));
+    report_warnings(input_filename, source_code, &program.warnings);
+    program
+}
+
+// Compiles the program, exiting with rendered diagnostics if it has errors.
+fn compile(input_filename: &str, source_code: &str, options: &CompileOptions) -> CompiledProgram {
+    let program = compiler::compile(source_code, options)
+        .unwrap_or_else(|errors| report_and_exit(input_filename, source_code, &errors));
+    report_warnings(input_filename, source_code, &program.warnings);
+    program
+}
+
+fn report_warnings(filename: &str, source: &str, warnings: &[Diagnostic]) {
+    for warning in warnings {
+        eprint!("{}", warning.render(filename, source));
+    }
+}
+
+// Executes the program on the built-in CPU model and prints every variable.
+fn run_program(compiled: &CompiledProgram) -> io::Result<()> {
+    let program = Program::parse(&compiled.asm).unwrap_or_else(|err| fail(err));
+    let mut cpu = Cpu::new();
+    if let Err(err) = cpu.run(&program, MAX_SIM_STEPS) {
+        fail(err);
+    }
+    for (name, loc) in compiled.symbols.variables() {
+        println!("{} = {}", name, cpu.memory[loc as usize]);
+    }
     Ok(())
 }
 
-fn read_file(filename: &str) -> io::Result<String> {
-    let mut file = File::open(filename)?;
+// Formats the program. A file is rewritten in place unless `-o` says
+// otherwise; stdin is formatted to stdout. With `--check`, nothing is
+// written and the run fails if the input is not already formatted.
+fn run_formatter(input_filename: &str, source_code: &str, options: &Options, logger: &Logger) -> io::Result<()> {
+    let formatted = formatter::format_source(source_code)
+        .unwrap_or_else(|errors| report_and_exit(input_filename, source_code, &errors));
+    if options.check {
+        if formatted != source_code {
+            eprintln!("{} is not formatted", input_filename);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    match (&options.output, &options.input) {
+        (Some(path), _) => write_output(Some(path), formatted.as_bytes(), logger),
+        (None, Some(path)) if formatted != source_code => {
+            File::create(path)?.write_all(formatted.as_bytes())?;
+            logger.log(Channel::Info, format!("Formatted {}", path));
+            Ok(())
+        }
+        (None, Some(_)) => Ok(()),
+        (None, None) => write_output(None, formatted.as_bytes(), logger),
+    }
+}
+
+// Fuzzes the compiler with a time-based seed, printing a minimized
+// reproducer for the first failure.
+fn run_fuzzer(iterations: &str) -> io::Result<()> {
+    let iterations: usize = iterations.parse().unwrap_or_else(|_| fail("iteration count must be a number"));
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    println!("fuzzing with seed {}", seed);
+    // Panics are caught and reported as failures, so keep the default hook
+    // from printing every one of them while the input is minimized.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = fuzz::fuzz(seed, iterations);
+    panic::set_hook(hook);
+    match result {
+        Ok(()) => {
+            println!("{} programs and their mutations compiled without failures", iterations);
+            Ok(())
+        }
+        Err(failure) => {
+            eprintln!("{}", failure);
+            process::exit(1);
+        }
+    }
+}
+
+fn report_and_exit(filename: &str, source: &str, errors: &[Diagnostic]) -> ! {
+    for error in errors {
+        eprint!("{}", error.render(filename, source));
+    }
+    eprintln!("could not compile {} due to {} previous error(s)", filename, errors.len());
+    process::exit(1);
+}
+
+// Reports an error that has no place in the source, such as one found while
+// running the program, and exits.
+fn fail(message: impl fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+// Reads the named file, or stdin for `None`.
+fn read_input(filename: Option<&str>) -> io::Result<String> {
     let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    match filename {
+        Some(filename) => File::open(filename)?.read_to_string(&mut contents)?,
+        None => io::stdin().read_to_string(&mut contents)?,
+    };
     Ok(contents)
 }
 
-fn generate_output_filename(input_filename: &str) -> String {
+// Writes to the named file, or stdout for `None` or `-`.
+fn write_output(path: Option<&str>, contents: &[u8], logger: &Logger) -> io::Result<()> {
+    match path {
+        Some(path) if path != "-" => {
+            File::create(path)?.write_all(contents)?;
+            logger.log(Channel::Info, format!("Output written to {}", path));
+            Ok(())
+        }
+        _ => io::stdout().write_all(contents),
+    }
+}
+
+fn generate_output_filename(input_filename: &str, extension: &str) -> String {
     let path = Path::new(input_filename);
-    let output_filename = path.with_extension("asm");
+    let output_filename = path.with_extension(extension);
     output_filename.to_string_lossy().into_owned()
 }
@@ -1,32 +1,195 @@
 
 use crate::lexer::{Token, Tokentype};
-use crate::ast::{ASTNode, NumberNode,VariableNode,VariableDec,BlockNode,ConditionalNode,BinaryOpNode};
-use std::rc::Rc;
+use crate::ast::{Expr, Stmt};
+use crate::context::CompilationContext;
+use crate::diagnostic::{Diagnostic, Span};
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+// Binding powers for the expression parser, weakest first. Operators of the
+// same power associate to the left.
+const COMPARISON: u8 = 10;
+const ADDITIVE: u8 = 20;
+const MULTIPLICATIVE: u8 = 30;
+pub const UNARY: u8 = 40;
+
+// How deeply statements and expressions may nest. Each link of a chain such
+// as `1 + 2 + 3` counts as a level too, since it puts the links before it one
+// level further down the tree. Every pass over the tree except lowering
+// recurses once per level, so this keeps them all well clear of the end of the
+// stack.
+pub const MAX_NESTING: usize = 256;
+
+// Infix operators, their source text and how tightly they bind. A new binary
+// operator only needs an entry here (and code generation for it).
+const INFIX_OPERATORS: &[(Tokentype, &str, u8)] = &[
+    (Tokentype::EQUAL, "==", COMPARISON),
+    (Tokentype::NOTEQUAL, "!=", COMPARISON),
+    (Tokentype::LESS, "<", COMPARISON),
+    (Tokentype::LESSEQUAL, "<=", COMPARISON),
+    (Tokentype::GREATER, ">", COMPARISON),
+    (Tokentype::GREATEREQUAL, ">=", COMPARISON),
+    (Tokentype::PLUS, "+", ADDITIVE),
+    (Tokentype::MINUS, "-", ADDITIVE),
+    (Tokentype::STAR, "*", MULTIPLICATIVE),
+    (Tokentype::SLASH, "/", MULTIPLICATIVE),
+    (Tokentype::PERCENT, "%", MULTIPLICATIVE),
+];
+
+fn infix_binding_power(typ: &Tokentype) -> Option<u8> {
+    INFIX_OPERATORS.iter().find(|(op, _, _)| op == typ).map(|&(_, _, bp)| bp)
+}
+
+// Binding power of the binary operator written `op`, as found in
+// `Expr::Binary`.
+pub fn binding_power(op: &str) -> Option<u8> {
+    INFIX_OPERATORS.iter().find(|&&(_, text, _)| text == op).map(|&(_, _, bp)| bp)
+}
 
 
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    ctx: CompilationContext,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    // The rules entered so far, once tracing is enabled.
+    trace: Option<Vec<String>>,
+    // Statements and expressions currently being parsed inside one another.
+    depth: usize,
+    // Set once `depth` passes `MAX_NESTING`. Parsing stops there, since
+    // recovering would only hit the limit again.
+    too_deep: bool,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+    // `tokens` normally comes from the lexer and ends with an END token. One
+    // is added after the last token if it is missing, so the parser can
+    // always look at the current token.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if !tokens.last().is_some_and(|token| token.typ == Tokentype::END) {
+            let span = tokens.last().map_or(Span::default(), |token| {
+                let end = token.span.end;
+                Span::new(end, end, token.span.line, token.span.column + (end - token.span.start))
+            });
+            tokens.push(Token { typ: Tokentype::END, value: String::new(), span });
+        }
+        Parser { tokens, pos: 0, ctx: CompilationContext::new(), errors: Vec::new(), warnings: Vec::new(), trace: None, depth: 0, too_deep: false }
+    }
+
+    // Hands the symbol table built while parsing over to code generation.
+    pub fn into_context(self) -> CompilationContext {
+        self.ctx
+    }
+
+    // Warnings collected while parsing; they do not stop compilation.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Records every grammar rule the parser enters, for `take_trace`.
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    // The trace so far, one line per rule entered or recovery made, e.g.
+    // `statement at 1:1 'int'`.
+    pub fn take_trace(&mut self) -> Vec<String> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn trace(&mut self, rule: &str) {
+        let token = &self.tokens[self.pos.min(self.tokens.len() - 1)];
+        if let Some(trace) = &mut self.trace {
+            trace.push(format!("{} at {}:{} {}", rule, token.span.line, token.span.column, describe(token)));
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Stmt, Vec<Diagnostic>> {
+        let (ast, errors) = self.parse_partial();
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Parses the whole program, recovering from syntax errors instead of
+    // stopping at the first one. Statements that failed to parse are left out
+    // of the returned tree, and every error is returned alongside it.
+    pub fn parse_partial(&mut self) -> (Stmt, Vec<Diagnostic>) {
+        let mut stmts = Vec::new();
+        let start = self.tokens[0].span;
+        while !self.is_at_end() {
+            match self.parse_stat() {
+                Ok(stat) => stmts.push(stat),
+                Err(err) => {
+                    self.errors.push(err);
+                    if self.too_deep {
+                        break;
+                    }
+                    self.synchronize();
+                    // A stray '}' at the top level has no block to close.
+                    self.match_types(&[Tokentype::RBRACE]);
+                }
+            }
+        }
+        let span = if self.pos > 0 { self.span_from(start) } else { Span::default() };
+        (Stmt::Block { stmts, span }, std::mem::take(&mut self.errors))
     }
 
-    pub fn parse(&mut self) -> Rc<dyn ASTNode> {
-        let mut block_node = BlockNode::new();
+    // Panic-mode recovery: skip tokens until just after the next ';' or up to
+    // the next '}' or statement keyword, so parsing can resume at a statement
+    // boundary.
+    fn synchronize(&mut self) {
+        self.trace("recover");
         while !self.is_at_end() {
-            block_node.add_stat(self.parse_stat());
+            if self.match_types(&[Tokentype::SEMICOLON]) {
+                break;
+            }
+            if self.check(Tokentype::RBRACE)
+                || self.check(Tokentype::INT)
+                || self.check(Tokentype::IF)
+                || self.check(Tokentype::WHILE)
+            {
+                break;
+            }
+            self.advance();
         }
-        Rc::new(block_node)
+        self.trace("resume");
+    }
+
+    // Runs `rule` one nesting level down, failing once that is too deep.
+    fn nested<T>(&mut self, rule: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        self.descend()?;
+        let result = rule(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Goes one nesting level down, failing once that is too deep.
+    fn descend(&mut self) -> ParseResult<()> {
+        if self.depth >= MAX_NESTING {
+            self.too_deep = true;
+            let message = format!("Statements and expressions nest more than {} levels deep.", MAX_NESTING);
+            return Err(Diagnostic::error(message, self.current().span));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn parse_stat(&mut self) -> ParseResult<Stmt> {
+        self.nested(Self::parse_stat_inner)
     }
 
-    fn parse_stat(&mut self) -> Rc<dyn ASTNode> {
+    fn parse_stat_inner(&mut self) -> ParseResult<Stmt> {
+        self.trace("statement");
         if self.match_types(&[Tokentype::INT]) {
             self.parse_var_dec()
         } else if self.match_types(&[Tokentype::IF]) {
             self.parse_cond()
+        } else if self.match_types(&[Tokentype::WHILE]) {
+            self.parse_while()
         } else if self.match_types(&[Tokentype::IDENTIFIER]) {
             self.parse_var_assign()
         } else {
@@ -34,103 +197,206 @@ impl Parser {
         }
     }
 
-    fn parse_var_assign(&mut self) -> Rc<dyn ASTNode> {
+    fn parse_var_assign(&mut self) -> ParseResult<Stmt> {
         let var_name = self.previous().value.clone();
+        let start = self.previous().span;
 
         // Semantic check: Ensure variable is declared
-        if !VariableNode::is_declared(&var_name) {
-            panic!("Semantic Error: Variable '{}' not declared.", var_name);
+        if !self.ctx.symbols.is_declared(&var_name) {
+            return Err(Diagnostic::error(
+                format!("Semantic Error: Variable '{}' not declared.", var_name),
+                self.previous().span,
+            ));
         }
 
-        self.consume(Tokentype::EQ, "Expected '=' after variable name.");
-        let val = self.parse_exp();
-        self.consume(Tokentype::SEMICOLON, "Expected ';' after expression.");
-        Rc::new(VariableDec::new(var_name, val))
+        self.consume(Tokentype::EQ, "Expected '=' after variable name.")?;
+        let val = self.parse_exp()?;
+        self.consume(Tokentype::SEMICOLON, "Expected ';' after expression.")?;
+        Ok(Stmt::Assign { name: var_name, val, span: self.span_from(start) })
     }
 
-    fn parse_var_dec(&mut self) -> Rc<dyn ASTNode> {
-
-        self.consume(Tokentype::IDENTIFIER, "Expected variable name.");
+    fn parse_var_dec(&mut self) -> ParseResult<Stmt> {
+        let start = self.previous().span;
+        self.consume(Tokentype::IDENTIFIER, "Expected variable name.")?;
         let var_name = self.previous().value.clone();
-        println!("Curr Position: {}", self.pos);
+        let name_span = self.previous().span;
         // Semantic check: Variable redeclaration check
-        if VariableNode::is_declared(&var_name) {
-            panic!("Semantic Error: Variable '{}' already declared.", var_name);
+        if self.ctx.symbols.is_declared(&var_name) {
+            return Err(Diagnostic::error(
+                format!("Semantic Error: Variable '{}' already declared.", var_name),
+                name_span,
+            ));
         }
 
+        let val = match self.parse_initializer() {
+            Ok(val) => val,
+            Err(err) => {
+                // Declare the name anyway so later uses don't report it as undeclared.
+                let _ = self.ctx.symbols.declare(&var_name);
+                return Err(err);
+            }
+        };
+        if let Err(err) = self.ctx.symbols.declare(&var_name) {
+            return Err(Diagnostic::error(format!("Semantic Error: {}.", err), name_span));
+        }
+        Ok(Stmt::Declare { name: var_name, val, span: self.span_from(start) })
+    }
+
+    fn parse_initializer(&mut self) -> ParseResult<Option<Expr>> {
+        // `int x;` starts the variable off at zero.
         let val = if self.match_types(&[Tokentype::EQ]) {
-            self.parse_exp()
+            Some(self.parse_exp()?)
         } else {
-            self.consume(Tokentype::SEMICOLON, "Expected ';' after variable declaration.");
-            Rc::new(VariableNode::new(var_name.clone()))
+            None
         };
 
-        println!("Curr Position: {}", self.pos);
-        self.consume(Tokentype::SEMICOLON, "Expected ';' after expression.");
-        Rc::new(VariableDec::new(var_name, val))
+        self.consume(Tokentype::SEMICOLON, "Expected ';' after variable declaration.")?;
+        Ok(val)
     }
 
-    fn parse_cond(&mut self) -> Rc<dyn ASTNode> {
-        self.consume(Tokentype::LPAREN, "Expected '(' after 'if'.");
-        let cond = self.parse_exp();
-        self.consume(Tokentype::RPAREN, "Expected ')' after condition.");
-        let then_branch = self.parse_block();
+    fn parse_cond(&mut self) -> ParseResult<Stmt> {
+        let start = self.previous().span;
+        self.consume(Tokentype::LPAREN, "Expected '(' after 'if'.")?;
+        let cond = self.parse_exp()?;
+        self.consume(Tokentype::RPAREN, "Expected ')' after condition.")?;
+        let then_branch = self.parse_block()?;
         let else_branch = if self.match_types(&[Tokentype::ELSE]) {
-            Some(self.parse_block())
+            Some(self.parse_block()?)
         } else {
             None
         };
 
-        Rc::new(ConditionalNode::new(cond, then_branch, else_branch))
+        Ok(Stmt::If {
+            cond,
+            then_branch: Box::new(then_branch),
+            else_branch: else_branch.map(Box::new),
+            span: self.span_from(start),
+        })
     }
 
-    fn parse_block(&mut self) -> Rc<dyn ASTNode> {
+    fn parse_while(&mut self) -> ParseResult<Stmt> {
+        let start = self.previous().span;
+        self.consume(Tokentype::LPAREN, "Expected '(' after 'while'.")?;
+        let cond = self.parse_exp()?;
+        self.consume(Tokentype::RPAREN, "Expected ')' after condition.")?;
+        let body = self.parse_block()?;
+
+        Ok(Stmt::While { cond, body: Box::new(body), span: self.span_from(start) })
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Stmt> {
+        self.trace("block");
         if self.match_types(&[Tokentype::LBRACE]) {
-            let mut block_node = BlockNode::new();
+            let start = self.previous().span;
+            let mut stmts = Vec::new();
             while !self.check(Tokentype::RBRACE) && !self.is_at_end() {
-                block_node.add_stat(self.parse_stat());
+                match self.parse_stat() {
+                    Ok(stat) => stmts.push(stat),
+                    Err(err) if self.too_deep => return Err(err),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.synchronize();
+                    }
+                }
             }
-            self.consume(Tokentype::RBRACE, "Expected '}' after block.");
-            Rc::new(block_node)
+            self.consume(Tokentype::RBRACE, "Expected '}' after block.")?;
+            Ok(Stmt::Block { stmts, span: self.span_from(start) })
         } else {
             self.parse_stat()
         }
     }
 
-    fn parse_exp_stat(&mut self) -> Rc<dyn ASTNode> {
-        let expr = self.parse_exp();
-        self.consume(Tokentype::SEMICOLON, "Expected ';' after expression.");
-        expr
+    fn parse_exp_stat(&mut self) -> ParseResult<Stmt> {
+        let expr = self.parse_exp()?;
+        self.consume(Tokentype::SEMICOLON, "Expected ';' after expression.")?;
+        Ok(Stmt::Expr(expr))
     }
 
-    fn parse_exp(&mut self) -> Rc<dyn ASTNode> {
-        let mut left = self.parse_prim(); 
+    fn parse_exp(&mut self) -> ParseResult<Expr> {
+        self.parse_exp_bp(0)
+    }
 
-        println!("Curr Position outside while: {}", self.pos);
+    // Precedence climbing: keep folding infix operators into `left` as long as
+    // they bind tighter than `min_bp`.
+    fn parse_exp_bp(&mut self, min_bp: u8) -> ParseResult<Expr> {
+        self.nested(|parser| parser.parse_exp_bp_inner(min_bp))
+    }
 
+    fn parse_exp_bp_inner(&mut self, min_bp: u8) -> ParseResult<Expr> {
+        self.trace(&format!("expression above {}", min_bp));
+        let left = self.parse_unary()?;
+        let depth = self.depth;
+        let result = self.parse_links(left, min_bp);
+        self.depth = depth;
+        result
+    }
 
-        while self.match_types(&[Tokentype::PLUS, Tokentype::MINUS, Tokentype::EQ, Tokentype::EQUAL]) {
+    // The rest of a chain starting with `left`. Each link goes a level down,
+    // and `parse_exp_bp_inner` gives the levels back once the chain ends.
+    fn parse_links(&mut self, mut left: Expr, min_bp: u8) -> ParseResult<Expr> {
+        while let Some(bp) = self.peek_infix() {
+            if bp <= min_bp {
+                break;
+            }
+            self.descend()?;
+            self.advance();
             let op = self.previous().value.clone();
-            let right = self.parse_prim();
-            println!("Curr Position inside while: {}", self.pos);
-            left = Rc::new(  BinaryOpNode::new(left, op, right));
+            let right = self.parse_exp_bp(bp)?;
+            left = Expr::binary(left, op, right);
         }
 
-        left
+        Ok(left)
     }
 
-    fn  parse_prim(&mut self) -> Rc<dyn ASTNode> {
+    fn peek_infix(&self) -> Option<u8> {
+        if self.is_at_end() {
+            return None;
+        }
+        infix_binding_power(&self.tokens[self.pos].typ)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        if self.match_types(&[Tokentype::MINUS]) {
+            let start = self.previous().span;
+            let operand = self.parse_exp_bp(UNARY)?;
+            let span = start.to(operand.span());
+            return Ok(Expr::Unary { op: "-".to_string(), operand: Box::new(operand), span });
+        }
+        self.parse_prim()
+    }
+
+    fn parse_prim(&mut self) -> ParseResult<Expr> {
+        self.trace("primary");
         if self.match_types(&[Tokentype::NUMBER]) {
-            println!("Number {}", self.previous().value);
-            Rc::new(NumberNode::new(self.previous().value.parse().unwrap()))
+            let token = self.previous().clone();
+            let val: i32 = match token.value.parse() {
+                Ok(val) if val <= 255 => val,
+                _ => {
+                    let warning = Diagnostic::warning(
+                        format!("Number {} does not fit in 8 bits and will wrap around.", token.value),
+                        token.span,
+                    );
+                    self.warnings.push(warning);
+                    token.value.bytes().fold(0, |acc, d| (acc * 10 + (d - b'0') as i32) % 256)
+                }
+            };
+            Ok(Expr::Number { val: val as u8, span: token.span })
         } else if self.match_types(&[Tokentype::IDENTIFIER]) {
-            Rc::new(VariableNode::new(self.previous().value.clone()))
+            let name = self.previous().value.clone();
+            let span = self.previous().span;
+            if !self.ctx.symbols.is_declared(&name) {
+                return Err(Diagnostic::error(
+                    format!("Semantic Error: Variable '{}' not declared.", name),
+                    span,
+                ));
+            }
+            Ok(Expr::Variable { name, span })
         } else if self.match_types(&[Tokentype::LPAREN]) {
-            let expr = self.parse_exp();
-            self.consume(Tokentype::RPAREN, "Expected ')' after expression.");
-            expr
+            let expr = self.parse_exp()?;
+            self.consume(Tokentype::RPAREN, "Expected ')' after expression.")?;
+            Ok(expr)
         } else {
-            panic!("Unexpected token '{}'", self.tokens[self.pos].value);
+            Err(self.error_at_current("Expected expression."))
         }
     }
 
@@ -157,6 +423,11 @@ impl Parser {
         self.tokens[self.pos].typ == typ
     }
 
+    // Span running from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
+    }
+
     fn previous(&self) -> &Token {
         &self.tokens[self.pos - 1]
     }
@@ -167,12 +438,177 @@ impl Parser {
         }
     }
 
-    fn consume(&mut self, typ: Tokentype, error_message: &str) {
+    fn consume(&mut self, typ: Tokentype, error_message: &str) -> ParseResult<()> {
         if self.check(typ) {
             self.advance();
+            Ok(())
         } else {
-            panic!("{} Found '{}'", error_message, self.tokens[self.pos].value);
+            Err(self.error_at_current(error_message))
         }
     }
+
+    fn current(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn error_at_current(&self, message: &str) -> Diagnostic {
+        let token = self.current();
+        Diagnostic::error(format!("{} Found {}", message, describe(token)), token.span)
+    }
 }
 
+fn describe(token: &Token) -> String {
+    if token.typ == Tokentype::END {
+        "end of input".to_string()
+    } else {
+        format!("'{}'", token.value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::test_support::{compile, parse, parse_errors};
+
+    #[test]
+    fn test_missing_semicolon_is_reported() {
+        let errors = parse_errors("int x = 1\nx = 2;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Expected ';' after variable declaration. Found 'x'");
+        assert_eq!(errors[0].span, Span::new(10, 11, 2, 1));
+    }
+
+    #[test]
+    fn test_undeclared_variable_is_reported() {
+        let errors = parse_errors("int x = y + 1;");
+        assert_eq!(errors[0].message, "Semantic Error: Variable 'y' not declared.");
+        assert_eq!(errors[0].span, Span::new(8, 9, 1, 9));
+    }
+
+    #[test]
+    fn test_declaration_without_initializer() {
+        assert!(parse_errors("int x; x = 3;").is_empty());
+    }
+
+    #[test]
+    fn test_program_span_covers_all_statements() {
+        let ast = parse("int x = 1;\nif (x == 1) { x = 2; }\n");
+        assert_eq!(ast.span(), Span::new(0, 33, 1, 1));
+    }
+
+    #[test]
+    fn test_token_lists_without_end() {
+        let ast = Parser::new(Vec::new()).parse().unwrap();
+        assert_eq!(ast.span(), Span::default());
+        // The missing END goes just after the last token.
+        let mut tokens = Lexer::new("int x = 1".to_string()).tokenize().unwrap();
+        tokens.pop();
+        let errors = Parser::new(tokens).parse().err().unwrap();
+        assert_eq!(errors[0].message, "Expected ';' after variable declaration. Found end of input");
+        assert_eq!(errors[0].span, Span::new(9, 9, 1, 10));
+    }
+
+    #[test]
+    fn test_comparison_binds_looser_than_addition() {
+        // `a == b + 1` must compare `a` against `b + 1`, not add 1 to `a == b`.
+        let asm = compile("int a = 1; int b = 0; if (a == b + 1) { a = 2; }").asm;
+        assert!(asm.contains("lda 2\nldi B 1\nadd\nsta 3\nlda 1\nmov B M 3\ncmp\n"));
+    }
+
+    #[test]
+    fn test_additive_operators_are_left_associative() {
+        // `a - b + 1` is `(a - b) + 1`.
+        let asm = compile("int a = 5; int b = 2; a = a - b + 1;").asm;
+        assert!(asm.contains("lda 1\nmov B M 2\nsub\nldi B 1\nadd\nsta 1\n"));
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        let asm = compile("int a = 5; a = -a;").asm;
+        assert!(asm.contains("ldi A 0\nmov B M 1\nsub\nsta 1\n"));
+    }
+
+    #[test]
+    fn test_assignment_is_not_an_expression() {
+        let errors = parse_errors("int a = 1; a = a = 2;");
+        assert_eq!(errors[0].message, "Expected ';' after expression. Found '='");
+    }
+
+    #[test]
+    fn test_reports_every_syntax_error() {
+        let errors = parse_errors(
+            "int x = 1\nint y = 2;\nif (x == y) {\n  y = ;\n  x = 3;\n}\nx = 4 4;\n",
+        );
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Expected ';' after variable declaration. Found 'int'",
+                "Expected expression. Found ';'",
+                "Expected ';' after expression. Found '4'",
+            ]
+        );
+        assert_eq!(errors[1].span.line, 4);
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let parens = |depth: usize| format!("int x = {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let negations = |depth: usize| format!("int x = {}1;", "-".repeat(depth));
+        let ifs = |depth: usize| format!("int x = 1; {}{}", "if (x) { ".repeat(depth), "}".repeat(depth));
+        let chain = |depth: usize| format!("int x = 1{};", " + 1".repeat(depth));
+        for source in [parens(5000), negations(5000), ifs(5000), chain(5000)] {
+            let errors = parse_errors(&source);
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].message, "Statements and expressions nest more than 256 levels deep.");
+        }
+        // Nesting below the limit is fine.
+        for source in [parens(200), negations(200), ifs(200), chain(200)] {
+            assert!(parse_errors(&source).is_empty());
+        }
+        // Levels add up however they are made.
+        let mixed = format!("int x = {}1{};", "(1 + ".repeat(130), ")".repeat(130));
+        assert_eq!(parse_errors(&mixed).len(), 1);
+    }
+
+    #[test]
+    fn test_recovers_from_stray_closing_brace() {
+        let errors = parse_errors("int x = 1; } x = 2 }");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_partial_ast_keeps_valid_statements() {
+        let tokens = Lexer::new("int x = 7;\nint y = ;\nx = y + 1;".to_string()).tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let (ast, errors) = parser.parse_partial();
+        assert_eq!(errors.len(), 1);
+        let mut ctx = parser.into_context();
+        let asm = crate::generate_asm(&ast, &mut ctx).unwrap();
+        assert!(asm.contains("ldi A 7\nsta 1"));
+        assert!(asm.contains("lda 2\nldi B 1\nadd\nsta 1"));
+    }
+
+    #[test]
+    fn test_trace_records_rules_and_recovery() {
+        let tokens = Lexer::new("int x = 1;
+x = ;".to_string()).tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.enable_trace();
+        assert!(parser.parse().is_err());
+        let expected = vec![
+            "statement at 1:1 'int'",
+            "expression above 0 at 1:9 '1'",
+            "primary at 1:9 '1'",
+            "statement at 2:1 'x'",
+            "expression above 0 at 2:5 ';'",
+            "primary at 2:5 ';'",
+            "recover at 2:5 ';'",
+            "resume at 2:6 end of input",
+        ];
+        assert_eq!(parser.take_trace(), expected);
+        assert!(Parser::new(Vec::new()).take_trace().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+
+// Peephole optimization over the assembly listing, run after instruction
+// selection when `CompileOptions::opt_level` is 2 or more.
+//
+// The listing is rewritten until nothing more applies:
+//
+//   - a load right after a store of A to the same cell is dropped, since A
+//     still holds the value (`sta 1` / `lda 1`);
+//   - a jump to a label that follows it with no instruction in between is
+//     dropped (`jmp %endif_0` / `else_0:` / `endif_0:`);
+//   - a label nothing jumps to or calls is dropped, which lets the first rule
+//     see across it;
+//   - a store is dropped when the same cell is stored again before anything
+//     reads it or control can leave the straight-line code in between.
+//
+// Loads and stores leave the flags alone, so none of this changes what a
+// later conditional jump sees.
+
+// What a line of the listing does, as far as the rules care.
+#[derive(Clone, Copy, PartialEq)]
+enum Line<'a> {
+    // Blank lines, comments and directives.
+    Empty,
+    Label(&'a str),
+    // `jmp` and the conditional jumps.
+    Jump(&'a str),
+    Call(&'a str),
+    // `ret` and `hlt`.
+    Exit,
+    // `lda` and `mov <reg> M`: the register and the cell.
+    Load(&'a str, &'a str),
+    // `sta` and `mov M <reg>`: the register and the cell.
+    Store(&'a str, &'a str),
+    // Anything else, which touches registers and flags only.
+    Other,
+}
+
+fn classify(line: &str) -> Line<'_> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    if code.is_empty() || code.starts_with('.') {
+        return Line::Empty;
+    }
+    if let Some(name) = code.strip_suffix(':') {
+        return Line::Label(name);
+    }
+    match code.split_whitespace().collect::<Vec<_>>()[..] {
+        ["jmp" | "jz" | "jnz" | "jc" | "jnc", target] => Line::Jump(target.strip_prefix('%').unwrap_or(target)),
+        ["call", target] => Line::Call(target.strip_prefix('%').unwrap_or(target)),
+        ["ret"] | ["hlt"] => Line::Exit,
+        ["lda", cell] => Line::Load("A", cell),
+        ["mov", reg, "M", cell] => Line::Load(reg, cell),
+        ["sta", cell] => Line::Store("A", cell),
+        ["mov", "M", reg, cell] => Line::Store(reg, cell),
+        _ => Line::Other,
+    }
+}
+
+// Optimizes `asm` and returns the new listing with the number of instructions
+// removed. Labels, directives and comments are not counted.
+pub fn optimize(asm: &str) -> (String, usize) {
+    let mut lines: Vec<(&str, Line)> = asm.lines().map(|line| (line, classify(line))).collect();
+    let mut saved = 0;
+    // Each round removes every line that is redundant in the listing as it
+    // stood. No rule relies on a line that another rule removes in the same
+    // round: a store that a load relies on is read by that load, so it is not
+    // dead, and a label that a jump relies on is referenced by that jump.
+    loop {
+        let kinds: Vec<Line> = lines.iter().map(|&(_, kind)| kind).collect();
+        let targets: HashSet<&str> = kinds
+            .iter()
+            .filter_map(|kind| match *kind {
+                Line::Jump(name) | Line::Call(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let before = lines.len();
+        let mut i = 0;
+        lines.retain(|&(_, kind)| {
+            i += 1;
+            if !removable(&kinds, i - 1, &targets) {
+                return true;
+            }
+            if !matches!(kind, Line::Label(_)) {
+                saved += 1;
+            }
+            false
+        });
+        if lines.len() == before {
+            break;
+        }
+    }
+    let mut out = lines.iter().map(|&(line, _)| line).collect::<Vec<_>>().join("\n");
+    if asm.ends_with('\n') {
+        out.push('\n');
+    }
+    (out, saved)
+}
+
+// `targets` holds every label some jump or call refers to.
+fn removable(lines: &[Line], i: usize, targets: &HashSet<&str>) -> bool {
+    match lines[i] {
+        Line::Label(name) => !targets.contains(name),
+        Line::Jump(name) => falls_through_to(&lines[i + 1..], name),
+        Line::Load("A", cell) => {
+            let previous = lines[..i].iter().rev().find(|&&line| line != Line::Empty);
+            previous == Some(&Line::Store("A", cell))
+        }
+        Line::Store(_, cell) => stored_again(&lines[i + 1..], cell),
+        _ => false,
+    }
+}
+
+// True if `rest` reaches the label `name` without executing an instruction.
+fn falls_through_to(rest: &[Line], name: &str) -> bool {
+    for &line in rest {
+        match line {
+            Line::Label(other) if other == name => return true,
+            Line::Label(_) | Line::Empty => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+// True if `rest` overwrites `cell` before reading it or leaving straight-line
+// code. Labels do not matter here: whatever jumps in still reaches the store.
+fn stored_again(rest: &[Line], cell: &str) -> bool {
+    for &line in rest {
+        match line {
+            Line::Store(_, other) if other == cell => return true,
+            Line::Load(_, other) if other == cell => return false,
+            Line::Jump(_) | Line::Call(_) | Line::Exit => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_program() {
+        let asm = "\
+.text
+ldi A 42
+sta 1
+lda 1
+ldi B 5
+add
+sta 2
+lda 1
+mov B M 2
+cmp
+jnz %else_0
+lda 2
+ldi B 1
+add
+sta 2
+jmp %endif_0
+else_0:
+endif_0:
+hlt
+";
+        let expected = "\
+.text
+ldi A 42
+sta 1
+ldi B 5
+add
+sta 2
+lda 1
+mov B M 2
+cmp
+jnz %else_0
+lda 2
+ldi B 1
+add
+sta 2
+else_0:
+hlt
+";
+        assert_eq!(optimize(asm), (expected.to_string(), 2));
+    }
+
+    #[test]
+    fn test_labels_block_load_removal() {
+        // `loop` can be reached with anything in A.
+        let asm = "sta 1\nloop:\nlda 1\njmp %loop\n";
+        assert_eq!(optimize(asm), (asm.to_string(), 0));
+        // Once nothing jumps there, the load goes too.
+        assert_eq!(optimize("sta 1 ; x\nunused:\nlda 1\nhlt\n"), ("sta 1 ; x\nhlt\n".to_string(), 1));
+        // Loading into B, or after storing B, is not redundant.
+        let asm = "sta 1\nmov B M 1\nmov M B 2\nlda 2\nhlt\n";
+        assert_eq!(optimize(asm), (asm.to_string(), 0));
+    }
+
+    #[test]
+    fn test_dead_stores() {
+        assert_eq!(optimize("ldi A 1\nsta 1\nldi A 2\nsta 1\nhlt\n"), ("ldi A 1\nldi A 2\nsta 1\nhlt\n".to_string(), 1));
+        // Read in between, left by a branch, or the last store before `hlt`.
+        for asm in [
+            "sta 1\nmov B M 1\nsta 1\nhlt\n",
+            "sta 1\njz %end\nsta 1\nend:\nhlt\n",
+            "sta 1\ncall %f\nsta 1\nhlt\nf:\nret\n",
+            "sta 1\nhlt\n",
+        ] {
+            assert_eq!(optimize(asm), (asm.to_string(), 0), "{}", asm);
+        }
+    }
+
+    #[test]
+    fn test_conditional_jump_to_next_line() {
+        assert_eq!(optimize("cmp\njz %next\nnext:\nhlt\n"), ("cmp\nhlt\n".to_string(), 1));
+    }
+}
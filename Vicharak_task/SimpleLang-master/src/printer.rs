@@ -0,0 +1,314 @@
+use std::fmt::Write;
+use crate::ast::{walk_expr, walk_stmt, Expr, Stmt, Visitor};
+use crate::diagnostic::Span;
+
+// Dumps of the syntax tree for debugging the parser and for external tools:
+// indented S-expressions for people, JSON (with source spans) for programs.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AstFormat {
+    Text,
+    Json,
+}
+
+pub fn dump_ast(ast: &Stmt, format: AstFormat) -> String {
+    match format {
+        AstFormat::Text => to_sexpr(ast),
+        AstFormat::Json => to_json(ast),
+    }
+}
+
+// One node per line, children indented under their parent:
+//
+//   (block
+//     (declare x
+//       (+
+//         (num 1)
+//         (var y))))
+pub fn to_sexpr(ast: &Stmt) -> String {
+    let mut printer = SExprPrinter { out: String::new(), depth: 0 };
+    printer.visit_stmt(ast);
+    printer.out.push('\n');
+    printer.out
+}
+
+struct SExprPrinter {
+    out: String,
+    depth: usize,
+}
+
+impl SExprPrinter {
+    fn open(&mut self, head: &str) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out.push_str(&"  ".repeat(self.depth));
+        self.out.push('(');
+        self.out.push_str(head);
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.out.push(')');
+        self.depth -= 1;
+    }
+}
+
+impl Visitor for SExprPrinter {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Declare { name, .. } => self.open(&format!("declare {}", name)),
+            Stmt::Assign { name, .. } => self.open(&format!("assign {}", name)),
+            Stmt::If { .. } => self.open("if"),
+            Stmt::While { .. } => self.open("while"),
+            Stmt::Block { .. } => self.open("block"),
+            Stmt::Expr(_) => self.open("expr"),
+        }
+        walk_stmt(self, stmt);
+        self.close();
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number { val, .. } => self.open(&format!("num {}", val)),
+            Expr::Variable { name, .. } => self.open(&format!("var {}", name)),
+            Expr::Binary { op, .. } | Expr::Unary { op, .. } => self.open(op),
+        }
+        walk_expr(self, expr);
+        self.close();
+    }
+}
+
+// A JSON value, just enough to serialize the tree without a dependency.
+enum Json {
+    Null,
+    Num(i64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = |out: &mut String, depth: usize| out.push_str(&"  ".repeat(depth));
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Num(n) => write!(out, "{}", n).unwrap(),
+            Json::Str(s) => write_json_string(out, s),
+            Json::Arr(items) if items.is_empty() => out.push_str("[]"),
+            Json::Arr(items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    indent(out, depth + 1);
+                    item.write(out, depth + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                indent(out, depth);
+                out.push(']');
+            }
+            Json::Obj(fields) => {
+                out.push_str("{\n");
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    indent(out, depth + 1);
+                    write_json_string(out, key);
+                    out.push_str(": ");
+                    val.write(out, depth + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                indent(out, depth);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Every node is an object with a "kind" and a "span"; the remaining keys
+// mirror the fields of the `Stmt`/`Expr` variant.
+pub fn to_json(ast: &Stmt) -> String {
+    let mut out = String::new();
+    stmt_json(ast).write(&mut out, 0);
+    out.push('\n');
+    out
+}
+
+fn node(kind: &str, span: Span, mut fields: Vec<(&'static str, Json)>) -> Json {
+    let span = Json::Obj(vec![
+        ("start", Json::Num(span.start as i64)),
+        ("end", Json::Num(span.end as i64)),
+        ("line", Json::Num(span.line as i64)),
+        ("column", Json::Num(span.column as i64)),
+    ]);
+    fields.insert(0, ("kind", Json::Str(kind.to_string())));
+    fields.insert(1, ("span", span));
+    Json::Obj(fields)
+}
+
+fn stmt_json(stmt: &Stmt) -> Json {
+    let span = stmt.span();
+    match stmt {
+        Stmt::Declare { name, val, .. } => node(
+            "Declare",
+            span,
+            vec![("name", Json::Str(name.clone())), ("val", val.as_ref().map_or(Json::Null, expr_json))],
+        ),
+        Stmt::Assign { name, val, .. } => {
+            node("Assign", span, vec![("name", Json::Str(name.clone())), ("val", expr_json(val))])
+        }
+        Stmt::If { cond, then_branch, else_branch, .. } => node(
+            "If",
+            span,
+            vec![
+                ("cond", expr_json(cond)),
+                ("then_branch", stmt_json(then_branch)),
+                ("else_branch", else_branch.as_deref().map_or(Json::Null, stmt_json)),
+            ],
+        ),
+        Stmt::While { cond, body, .. } => {
+            node("While", span, vec![("cond", expr_json(cond)), ("body", stmt_json(body))])
+        }
+        Stmt::Block { stmts, .. } => node("Block", span, vec![("stmts", Json::Arr(stmts.iter().map(stmt_json).collect()))]),
+        Stmt::Expr(expr) => node("Expr", span, vec![("expr", expr_json(expr))]),
+    }
+}
+
+fn expr_json(expr: &Expr) -> Json {
+    let span = expr.span();
+    match expr {
+        Expr::Number { val, .. } => node("Number", span, vec![("val", Json::Num(*val as i64))]),
+        Expr::Variable { name, .. } => node("Variable", span, vec![("name", Json::Str(name.clone()))]),
+        Expr::Binary { l, op, r, .. } => node(
+            "Binary",
+            span,
+            vec![("l", expr_json(l)), ("op", Json::Str(op.clone())), ("r", expr_json(r))],
+        ),
+        Expr::Unary { op, operand, .. } => {
+            node("Unary", span, vec![("op", Json::Str(op.clone())), ("operand", expr_json(operand))])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
+
+    #[test]
+    fn test_sexpr_dump() {
+        let ast = parse("int x = 42;\nint y;\nif (x == y + 1) { y = -x; } else { while (y) { y = y - 1; } }");
+        let expected = "\
+(block
+  (declare x
+    (num 42))
+  (declare y)
+  (if
+    (==
+      (var x)
+      (+
+        (var y)
+        (num 1)))
+    (block
+      (assign y
+        (-
+          (var x))))
+    (block
+      (while
+        (var y)
+        (block
+          (assign y
+            (-
+              (var y)
+              (num 1))))))))
+";
+        assert_eq!(to_sexpr(&ast), expected);
+    }
+
+    #[test]
+    fn test_json_dump() {
+        let ast = parse("int x;\nx = x + 1;");
+        let expected = r#"{
+  "kind": "Block",
+  "span": {
+    "start": 0,
+    "end": 17,
+    "line": 1,
+    "column": 1
+  },
+  "stmts": [
+    {
+      "kind": "Declare",
+      "span": {
+        "start": 0,
+        "end": 6,
+        "line": 1,
+        "column": 1
+      },
+      "name": "x",
+      "val": null
+    },
+    {
+      "kind": "Assign",
+      "span": {
+        "start": 7,
+        "end": 17,
+        "line": 2,
+        "column": 1
+      },
+      "name": "x",
+      "val": {
+        "kind": "Binary",
+        "span": {
+          "start": 11,
+          "end": 16,
+          "line": 2,
+          "column": 5
+        },
+        "l": {
+          "kind": "Variable",
+          "span": {
+            "start": 11,
+            "end": 12,
+            "line": 2,
+            "column": 5
+          },
+          "name": "x"
+        },
+        "op": "+",
+        "r": {
+          "kind": "Number",
+          "span": {
+            "start": 15,
+            "end": 16,
+            "line": 2,
+            "column": 9
+          },
+          "val": 1
+        }
+      }
+    }
+  ]
+}
+"#;
+        assert_eq!(to_json(&ast), expected);
+    }
+
+    #[test]
+    fn test_json_strings_are_escaped() {
+        let mut out = String::new();
+        write_json_string(&mut out, "a\"b\\c\n\u{1}");
+        assert_eq!(out, r#""a\"b\\c\n\u0001""#);
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::{self, Write};
+use crate::context::CompilationContext;
+
+// Helper routines for the operators the CPU has no instruction for. They are
+// called with the left operand in A and the right operand in B, return their
+// result in A, and are only appended to the program when it uses them.
+//
+// Both routines loop over the 8 bits of an operand from the top down, using
+// only `add`, `sub` and `cmp`: doubling a value is `add` with itself, and its
+// top bit is clear exactly when `cmp` against 128 borrows.
+
+// Which routines the program calls. Filled in during code generation.
+pub struct RuntimeLibrary {
+    uses_mul: bool,
+    uses_div: bool,
+}
+
+impl RuntimeLibrary {
+    pub fn new() -> Self {
+        RuntimeLibrary { uses_mul: false, uses_div: false }
+    }
+}
+
+impl Default for RuntimeLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Emits a call to the shift-and-add multiply: A = A * B (mod 256).
+pub fn call_mul(out: &mut dyn Write, ctx: &mut CompilationContext) -> io::Result<()> {
+    ctx.runtime.uses_mul = true;
+    writeln!(out, "call %__mul")
+}
+
+// Emits a call to the restoring division: A = A / B. Division by zero
+// yields 255.
+pub fn call_div(out: &mut dyn Write, ctx: &mut CompilationContext) -> io::Result<()> {
+    ctx.runtime.uses_div = true;
+    writeln!(out, "call %__div")
+}
+
+// Emits a call to the division routine and fetches the remainder: A = A % B.
+// `x % 0` is `x`.
+pub fn call_mod(out: &mut dyn Write, ctx: &mut CompilationContext) -> io::Result<()> {
+    call_div(out, ctx)?;
+    writeln!(out, "lda {}", ctx.symbols.declare("__div_rem")?)
+}
+
+// Appends the routines used by the program. Their scratch cells are taken
+// from the symbol table under names the lexer can never produce.
+pub fn generate_runtime(out: &mut dyn Write, ctx: &mut CompilationContext) -> io::Result<()> {
+    if ctx.runtime.uses_mul {
+        let x = ctx.symbols.declare("__mul_x")?;
+        let y = ctx.symbols.declare("__mul_y")?;
+        let r = ctx.symbols.declare("__mul_r")?;
+        let n = ctx.symbols.declare("__mul_n")?;
+        write!(out, "\
+__mul:
+sta {x}
+mov M B {y}
+ldi A 0
+sta {r}
+ldi A 8
+sta {n}
+__mul_loop:
+lda {r}
+mov B M {r}
+add
+sta {r}
+lda {y}
+ldi B 128
+cmp
+jc %__mul_skip
+lda {r}
+mov B M {x}
+add
+sta {r}
+__mul_skip:
+lda {y}
+mov B M {y}
+add
+sta {y}
+lda {n}
+ldi B 1
+sub
+sta {n}
+jnz %__mul_loop
+lda {r}
+ret
+")?;
+    }
+
+    if ctx.runtime.uses_div {
+        let q = ctx.symbols.declare("__div_q")?;
+        let d = ctx.symbols.declare("__div_d")?;
+        let rem = ctx.symbols.declare("__div_rem")?;
+        let ov = ctx.symbols.declare("__div_ov")?;
+        let n = ctx.symbols.declare("__div_n")?;
+        // The remainder is doubled every step; when that carries out of 8 bits
+        // the true remainder is at least 256 > divisor, so `ov` forces the
+        // subtraction, which wraps to the right value.
+        write!(out, "\
+__div:
+sta {q}
+mov M B {d}
+ldi A 0
+sta {rem}
+ldi A 8
+sta {n}
+__div_loop:
+lda {rem}
+mov B M {rem}
+add
+sta {rem}
+ldi A 0
+jnc %__div_no_carry
+ldi A 1
+__div_no_carry:
+sta {ov}
+lda {q}
+ldi B 128
+cmp
+jc %__div_shift
+lda {rem}
+ldi B 1
+add
+sta {rem}
+__div_shift:
+lda {q}
+mov B M {q}
+add
+sta {q}
+lda {ov}
+ldi B 1
+cmp
+jz %__div_sub
+lda {rem}
+mov B M {d}
+cmp
+jc %__div_next
+__div_sub:
+lda {rem}
+mov B M {d}
+sub
+sta {rem}
+lda {q}
+ldi B 1
+add
+sta {q}
+__div_next:
+lda {n}
+ldi B 1
+sub
+sta {n}
+jnz %__div_loop
+lda {q}
+ret
+")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::compile;
+
+    #[test]
+    fn test_runtime_only_appended_when_used() {
+        let asm = compile("int a = 6; int b = a + 1;").asm;
+        assert!(!asm.contains("__mul"));
+        assert!(!asm.contains("__div"));
+    }
+
+    #[test]
+    fn test_multiply_calls_routine() {
+        let asm = compile("int a = 6; int b = a * 7;").asm;
+        assert!(asm.contains("lda 1\nldi B 7\ncall %__mul\nsta 2\nhlt\n__mul:\n"));
+        assert!(!asm.contains("__div:"));
+    }
+
+    #[test]
+    fn test_modulo_reads_remainder_cell() {
+        // a, b and c take cells 1-3. `a % b` is evaluated first and claims
+        // cell 4 for the remainder, then its result is parked in temporary 5.
+        let asm = compile("int a = 7; int b = 2; int c = a / b + a % b;").asm;
+        assert!(asm.contains("call %__div\nlda 4\nsta 5\n"));
+        assert!(asm.contains("__div:\nsta 6\nmov M B 7\n"));
+        assert_eq!(asm.matches("__div:").count(), 1);
+    }
+
+    #[test]
+    fn test_multiplicative_binds_tighter_than_additive() {
+        let asm = compile("int a = 1; a = a + 2 * 3;").asm;
+        assert!(asm.contains("ldi A 2\nldi B 3\ncall %__mul\nsta 2\nlda 1\nmov B M 2\nadd\n"));
+    }
+}
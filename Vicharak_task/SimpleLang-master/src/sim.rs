@@ -0,0 +1,206 @@
+use std::fmt;
+use crate::assembler::{Instruction, Program, Reg};
+
+// A model of the 8-bit target CPU that runs programs read by the assembler's
+// first pass.
+//
+// The machine has two registers, A and B, a zero flag and a carry flag, a
+// program counter and 256 bytes of data memory. Programs live in their own
+// instruction memory, so data address 1 never overlaps code. `call` pushes the
+// return address on a separate hardware stack.
+//
+// `add`, `sub` and `cmp` compute the 9-bit result of A + B or A - B: Z is set
+// when the low 8 bits are zero and C when the result does not fit in 8 bits
+// (a carry for `add`, a borrow for `sub`/`cmp`, i.e. A < B). `cmp` discards
+// the result. No other instruction touches the flags.
+
+pub const MEMORY_SIZE: usize = 256;
+const STACK_LIMIT: usize = 64;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SimError {
+    pub message: String,
+}
+
+impl SimError {
+    fn new(message: String) -> Self {
+        SimError { message }
+    }
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub struct Cpu {
+    pub a: u8,
+    pub b: u8,
+    pub zero: bool,
+    pub carry: bool,
+    pub pc: usize,
+    pub memory: [u8; MEMORY_SIZE],
+    stack: Vec<usize>,
+    halted: bool,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Cpu {
+            a: 0,
+            b: 0,
+            zero: false,
+            carry: false,
+            pc: 0,
+            memory: [0; MEMORY_SIZE],
+            stack: Vec::new(),
+            halted: false,
+        }
+    }
+
+    fn register(&mut self, reg: Reg) -> &mut u8 {
+        match reg {
+            Reg::A => &mut self.a,
+            Reg::B => &mut self.b,
+        }
+    }
+
+    fn set_flags(&mut self, result: i16) {
+        self.carry = !(0..=255).contains(&result);
+        self.zero = result as u8 == 0;
+    }
+
+    // Executes one instruction.
+    pub fn step(&mut self, program: &Program) -> Result<(), SimError> {
+        let instruction = program
+            .instructions
+            .get(self.pc)
+            .ok_or_else(|| SimError::new(format!("program counter {} ran past the end of the program", self.pc)))?;
+        self.pc += 1;
+        match *instruction {
+            Instruction::Ldi(reg, val) => *self.register(reg) = val,
+            Instruction::Lda(addr) => self.a = self.memory[addr as usize],
+            Instruction::Sta(addr) => self.memory[addr as usize] = self.a,
+            Instruction::MovRegMem(reg, addr) => *self.register(reg) = self.memory[addr as usize],
+            Instruction::MovMemReg(addr, reg) => self.memory[addr as usize] = *self.register(reg),
+            Instruction::Add => {
+                let result = self.a as i16 + self.b as i16;
+                self.set_flags(result);
+                self.a = result as u8;
+            }
+            Instruction::Sub => {
+                let result = self.a as i16 - self.b as i16;
+                self.set_flags(result);
+                self.a = result as u8;
+            }
+            Instruction::Cmp => self.set_flags(self.a as i16 - self.b as i16),
+            Instruction::Jmp(target) => self.pc = target,
+            Instruction::Jz(target) => if self.zero { self.pc = target },
+            Instruction::Jnz(target) => if !self.zero { self.pc = target },
+            Instruction::Jc(target) => if self.carry { self.pc = target },
+            Instruction::Jnc(target) => if !self.carry { self.pc = target },
+            Instruction::Call(target) => {
+                if self.stack.len() >= STACK_LIMIT {
+                    return Err(SimError::new("call stack overflow".to_string()));
+                }
+                self.stack.push(self.pc);
+                self.pc = target;
+            }
+            Instruction::Ret => {
+                self.pc = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| SimError::new("'ret' with an empty call stack".to_string()))?;
+            }
+            Instruction::Hlt => self.halted = true,
+        }
+        Ok(())
+    }
+
+    // Runs until `hlt`, giving up after `max_steps` instructions so that a
+    // program that never halts is reported instead of hanging.
+    pub fn run(&mut self, program: &Program, max_steps: usize) -> Result<(), SimError> {
+        for _ in 0..max_steps {
+            if self.halted {
+                return Ok(());
+            }
+            self.step(program)?;
+        }
+        if self.halted {
+            Ok(())
+        } else {
+            Err(SimError::new(format!("program did not halt within {} steps", max_steps)))
+        }
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::compile;
+
+    fn run_asm(asm: &str) -> Cpu {
+        let program = Program::parse(asm).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.run(&program, 100_000).unwrap();
+        cpu
+    }
+
+    // Compiles and runs `source`, returning the final value of each variable.
+    fn run_source(source: &str, names: &[&str]) -> Vec<u8> {
+        let mut program = compile(source);
+        let cpu = run_asm(&program.asm);
+        names.iter().map(|name| cpu.memory[program.symbols.declare(name).unwrap() as usize]).collect()
+    }
+
+    #[test]
+    fn test_flags() {
+        let cpu = run_asm("ldi A 200\nldi B 100\nadd\nhlt\n");
+        assert_eq!((cpu.a, cpu.carry, cpu.zero), (44, true, false));
+        let cpu = run_asm("ldi A 3\nldi B 5\ncmp\nhlt\n");
+        assert_eq!((cpu.a, cpu.carry, cpu.zero), (3, true, false));
+        let cpu = run_asm("ldi A 5\nldi B 5\nsub\nhlt\n");
+        assert_eq!((cpu.a, cpu.carry, cpu.zero), (0, false, true));
+    }
+
+    #[test]
+    fn test_runs_input_program() {
+        let source = "int x = 42;\nint y = x + 5;\nif (x == y) {\n    y = y + 1;\n}\n";
+        assert_eq!(run_source(source, &["x", "y"]), vec![42, 47]);
+    }
+
+    #[test]
+    fn test_loops_and_comparisons() {
+        let source = "int i = 0; int sum = 0;
+                      while (i < 10) { sum = sum + i; i = i + 1; }
+                      int le = 0; if (i <= 10) { le = 1; }
+                      int gt = i > 10;";
+        assert_eq!(run_source(source, &["i", "sum", "le", "gt"]), vec![10, 45, 1, 0]);
+    }
+
+    #[test]
+    fn test_runtime_routines() {
+        let source = "int a = 23; int b = 7; int p = a * b; int q = a / b; int r = a % b; int z = a / 0;";
+        assert_eq!(run_source(source, &["p", "q", "r", "z"]), vec![161, 3, 2, 255]);
+    }
+
+    #[test]
+    fn test_nested_expressions() {
+        let source = "int a = 9; int b = 4; int c = (a - b) - (b - (a - 8));";
+        assert_eq!(run_source(source, &["c"]), vec![2]);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let program = Program::parse("loop:\njmp %loop\n").unwrap();
+        let err = Cpu::new().run(&program, 50).err().unwrap();
+        assert_eq!(err.message, "program did not halt within 50 steps");
+    }
+}
@@ -0,0 +1,19 @@
+use crate::ast::Stmt;
+use crate::diagnostic::Diagnostic;
+use crate::{CompileOptions, CompiledProgram};
+
+// The library's entry points with default options, for unit tests that start
+// from source text and expect it to be valid unless they say otherwise.
+
+pub fn parse(source: &str) -> Stmt {
+    crate::parse(source, &CompileOptions::default()).unwrap().ast
+}
+
+// Every error in `source`, or none.
+pub fn parse_errors(source: &str) -> Vec<Diagnostic> {
+    crate::parse(source, &CompileOptions::default()).err().unwrap_or_default()
+}
+
+pub fn compile(source: &str) -> CompiledProgram {
+    crate::compile(source, &CompileOptions::default()).unwrap()
+}